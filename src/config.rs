@@ -0,0 +1,492 @@
+use crate::jira::{self, ApiFlavor, IssueMatcher, JiraConnection, RetryPolicy};
+use crate::ledger;
+use crate::oauth::{self, OAuthConfig};
+use crate::rounding::{RoundingMode, RoundingPolicy};
+use crate::timewarrior;
+use crate::timezone::Timezone;
+use log::LevelFilter;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+use time::OffsetDateTime;
+
+/// All `twjp.*` configuration keys we understand
+const KNOWN_KEYS: &[&str] = &[
+    "twjp.url",
+    "twjp.user",
+    "twjp.token",
+    "twjp.token_command",
+    "twjp.token_file",
+    "twjp.is_pat",
+    "twjp.oauth.client_id",
+    "twjp.oauth.client_secret",
+    "twjp.oauth.port",
+    "twjp.oauth.token_file",
+    "twjp.api_version",
+    "twjp.api_flavor",
+    "twjp.timeout",
+    "twjp.max_retries",
+    "twjp.max_retry_delay",
+    "twjp.max_concurrency",
+    "twjp.log_level",
+    "twjp.skip_existing",
+    "twjp.uploaded_tag",
+    "twjp.timezone",
+    "twjp.issue_key_pattern",
+    "twjp.project_keys",
+    "twjp.ledger",
+    "twjp.aggregate",
+    "twjp.rounding.mode",
+    "twjp.rounding.increment",
+    "twjp.rounding.minimum",
+    "twjp.reconcile",
+    "twjp.delete_orphans",
+    "twjp.dry_run",
+];
+
+/// How intervals are combined into worklogs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Aggregation {
+    /// Every interval becomes its own worklog
+    None,
+    /// Intervals on the same issue and calendar day are summed into one worklog
+    Daily,
+}
+
+impl FromStr for Aggregation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Aggregation::None),
+            "daily" => Ok(Aggregation::Daily),
+            _ => Err("expected none or daily".to_string()),
+        }
+    }
+}
+
+/// The words passed to the extension as commands, e.g. `timew jirapush check`, rather than as tags to filter by
+const COMMANDS: &[&str] = &["check", "login", "dry-run"];
+
+/// Validated extension configuration, read from the TimeWarrior config block
+#[derive(Clone)]
+pub struct Config {
+    pub url: String,
+    pub user: String,
+    pub token: String,
+    pub is_pat: bool,
+    /// The OAuth app to authenticate with instead of the token, if configured
+    pub oauth: Option<OAuthConfig>,
+    pub api_version: String,
+    pub flavor: ApiFlavor,
+    /// The timeout for each request to Jira
+    pub timeout: Duration,
+    pub retry: RetryPolicy,
+    /// The number of worklogs handled at once
+    pub max_concurrency: usize,
+    pub log_level: LevelFilter,
+    pub skip_existing: bool,
+    pub uploaded_tag: String,
+    pub timezone: Timezone,
+    pub issue_matcher: IssueMatcher,
+    pub ledger: PathBuf,
+    pub aggregation: Aggregation,
+    pub rounding: RoundingPolicy,
+    pub reconcile: bool,
+    pub delete_orphans: bool,
+    /// Only report what would be done, requested with `twjp.dry_run` or `timew jirapush dry-run`
+    pub dry_run: bool,
+    /// Only check the connection and credentials, requested with `timew jirapush check`
+    pub check: bool,
+    /// Only log in with OAuth, requested with `timew jirapush login`
+    pub login: bool,
+    /// The reporting range requested on the command line, e.g. with `timew jirapush :week`.
+    /// Only intervals starting within it are synced.
+    pub range: (Option<OffsetDateTime>, Option<OffsetDateTime>),
+    /// The tags requested on the command line, all of which an interval needs to be synced
+    pub filter_tags: Vec<String>,
+}
+
+/// Masks the token, like `JiraConnection`
+impl std::fmt::Debug for Config {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Config")
+            .field("url", &self.url)
+            .field("user", &self.user)
+            .field("token", &"<redacted>")
+            .field("is_pat", &self.is_pat)
+            .field("oauth", &self.oauth)
+            .field("api_version", &self.api_version)
+            .field("flavor", &self.flavor)
+            .field("timeout", &self.timeout)
+            .field("retry", &self.retry)
+            .field("max_concurrency", &self.max_concurrency)
+            .field("log_level", &self.log_level)
+            .field("skip_existing", &self.skip_existing)
+            .field("uploaded_tag", &self.uploaded_tag)
+            .field("timezone", &self.timezone)
+            .field("issue_matcher", &self.issue_matcher)
+            .field("ledger", &self.ledger)
+            .field("aggregation", &self.aggregation)
+            .field("rounding", &self.rounding)
+            .field("reconcile", &self.reconcile)
+            .field("delete_orphans", &self.delete_orphans)
+            .field("dry_run", &self.dry_run)
+            .field("check", &self.check)
+            .field("login", &self.login)
+            .field("range", &self.range)
+            .field("filter_tags", &self.filter_tags)
+            .finish()
+    }
+}
+
+/// The environment variable which, if set, overrides any configured token
+pub const TOKEN_ENV_VAR: &str = "TWJP_TOKEN";
+
+/// The environment variable which, if set, overrides any configured OAuth client secret
+pub const OAUTH_CLIENT_SECRET_ENV_VAR: &str = "TWJP_OAUTH_CLIENT_SECRET";
+
+/// Resolves the API token from, in order of precedence, the `TWJP_TOKEN` environment variable,
+/// or one of `twjp.token_command`, `twjp.token_file`, or `twjp.token`.
+/// This keeps the token out of timewarrior.cfg, which is often kept in a dotfile repository.
+fn resolve_token(tw_conf: &HashMap<String, String>, env_token: Option<String>) -> Result<String, String> {
+    if let Some(token) = env_token.filter(|t| !t.is_empty()) {
+        return Ok(token);
+    }
+    let sources: Vec<(&str, &String)> = ["twjp.token", "twjp.token_command", "twjp.token_file"]
+        .iter()
+        .filter_map(|k| Some((*k, tw_conf.get(*k).filter(|v| !v.is_empty())?)))
+        .collect();
+    let token = match sources.as_slice() {
+        [] => return Err("Missing required config twjp.token".to_string()),
+        [("twjp.token", token)] => token.to_string(),
+        [("twjp.token_command", command)] => {
+            let output = std::process::Command::new("sh")
+                .args(["-c", command])
+                .output()
+                .map_err(|e| format!("Error running twjp.token_command {:?}: {}", command, e))?;
+            if !output.status.success() {
+                return Err(format!(
+                    "twjp.token_command {:?} failed ({}): {}",
+                    command,
+                    output.status,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ));
+            }
+            // Password managers such as pass keep the secret on the first line
+            String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .to_string()
+        }
+        [(_, path)] => {
+            let path = match path.strip_prefix("~/") {
+                Some(rest) => PathBuf::from(std::env::var("HOME").unwrap_or_default()).join(rest),
+                None => PathBuf::from(path),
+            };
+            std::fs::read_to_string(&path)
+                .map_err(|e| format!("Error reading twjp.token_file {}: {}", path.display(), e))?
+                .trim()
+                .to_string()
+        }
+        _ => {
+            return Err(
+                "Only one of twjp.token, twjp.token_command, and twjp.token_file may be set".to_string(),
+            )
+        }
+    };
+    match token.is_empty() {
+        true => Err(format!("The token from {} is empty", sources[0].0)),
+        false => Ok(token),
+    }
+}
+
+/// Parses a boolean the way TimeWarrior does
+fn parse_bool(val: &str) -> Result<bool, String> {
+    match val {
+        "on" | "1" | "yes" | "y" | "true" => Ok(true),
+        "off" | "0" | "no" | "n" | "false" => Ok(false),
+        _ => Err("expected on/off, yes/no, true/false or 1/0".to_string()),
+    }
+}
+
+/// Parses a positive number of seconds
+fn parse_seconds(val: &str) -> Result<Duration, String> {
+    match u64::from_str(val) {
+        Ok(s) if s > 0 => Ok(Duration::from_secs(s)),
+        _ => Err("expected a positive number of seconds".to_string()),
+    }
+}
+
+/// Parses a number of minutes into seconds, no less than the given minimum
+fn parse_minutes(val: &str, min: i64) -> Result<i64, String> {
+    match i64::from_str(val) {
+        Ok(m) if m >= min => Ok(m * 60),
+        _ => Err(format!("expected a number of minutes, at least {}", min)),
+    }
+}
+
+/// Determines the log level.
+/// Follows the "well-behaved" config guidelines, https://timewarrior.net/docs/api/#guidelines,
+/// unless overridden by `twjp.log_level`. Invalid values are ignored here and reported by validation.
+pub fn log_level(tw_conf: &HashMap<String, String>) -> LevelFilter {
+    if let Some(Ok(l)) = tw_conf.get("twjp.log_level").map(|v| LevelFilter::from_str(v)) {
+        return l;
+    }
+    let enabled = |key: &str| tw_conf.get(key).map(|v| parse_bool(v) == Ok(true)) == Some(true);
+    if enabled("debug") {
+        LevelFilter::Debug
+    } else if enabled("verbose") {
+        LevelFilter::Warn
+    } else {
+        LevelFilter::Error
+    }
+}
+
+/// Collects problems found while validating the configuration
+struct Validator<'a> {
+    tw_conf: &'a HashMap<String, String>,
+    errors: Vec<String>,
+}
+
+impl<'a> Validator<'a> {
+    /// Reads a required value
+    fn required(&mut self, key: &str) -> String {
+        match self.tw_conf.get(key) {
+            Some(v) if !v.is_empty() => v.clone(),
+            _ => {
+                self.errors.push(format!("Missing required config {}", key));
+                String::new()
+            }
+        }
+    }
+
+    /// Reads an optional value, reporting it if it can't be parsed
+    fn parse<T, E: Display>(
+        &mut self,
+        key: &str,
+        default: T,
+        parse: impl Fn(&str) -> Result<T, E>,
+    ) -> T {
+        match self.tw_conf.get(key) {
+            None => default,
+            Some(v) => match parse(v) {
+                Ok(t) => t,
+                Err(e) => {
+                    self.errors.push(format!("Invalid value {:?} for {}: {}", v, key, e));
+                    default
+                }
+            },
+        }
+    }
+}
+
+impl Config {
+    /// Builds and validates the configuration from TimeWarrior's config block and the process environment.
+    /// Every problem found is returned, so they can all be fixed at once.
+    pub fn from_tw_config(tw_conf: &HashMap<String, String>) -> Result<Config, Vec<String>> {
+        let env: HashMap<String, String> = [TOKEN_ENV_VAR, OAUTH_CLIENT_SECRET_ENV_VAR]
+            .iter()
+            .filter_map(|k| Some((k.to_string(), std::env::var(k).ok()?)))
+            .collect();
+        Config::from_tw_config_with_env(tw_conf, &env)
+    }
+
+    /// Like `from_tw_config`, with the given environment variables instead of the process environment
+    pub fn from_tw_config_with_env(
+        tw_conf: &HashMap<String, String>,
+        env: &HashMap<String, String>,
+    ) -> Result<Config, Vec<String>> {
+        let mut v = Validator {
+            tw_conf,
+            errors: Vec::new(),
+        };
+
+        // Report unknown keys, which are most likely typos
+        let mut unknown: Vec<&String> = tw_conf
+            .keys()
+            .filter(|k| k.starts_with("twjp.") && !KNOWN_KEYS.contains(&k.as_str()))
+            .collect();
+        unknown.sort();
+        for key in unknown {
+            v.errors.push(format!("Unknown config key {}", key));
+        }
+
+        let url = v.required("twjp.url");
+        // With OAuth, we're identified by the OAuth session rather than a user and token
+        let oauth = tw_conf.get("twjp.oauth.client_id").map(|client_id| OAuthConfig {
+            client_id: client_id.clone(),
+            client_secret: match env.get(OAUTH_CLIENT_SECRET_ENV_VAR) {
+                Some(secret) if !secret.is_empty() => secret.clone(),
+                _ => v.required("twjp.oauth.client_secret"),
+            },
+            port: v.parse("twjp.oauth.port", 8765, u16::from_str),
+            token_path: tw_conf
+                .get("twjp.oauth.token_file")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| ledger::data_dir(tw_conf).join(oauth::TOKEN_FILE)),
+            token_url: oauth::TOKEN_URL.to_string(),
+        });
+        let (user, token) = match oauth {
+            Some(_) => (tw_conf.get("twjp.user").cloned().unwrap_or_default(), String::new()),
+            None => {
+                let user = v.required("twjp.user");
+                let token = match resolve_token(tw_conf, env.get(TOKEN_ENV_VAR).cloned()) {
+                    Ok(token) => token,
+                    Err(e) => {
+                        v.errors.push(e);
+                        String::new()
+                    }
+                };
+                (user, token)
+            }
+        };
+        let is_pat = v.parse("twjp.is_pat", false, parse_bool);
+        // OAuth 2.0 (3LO) is only offered by Jira Cloud
+        let default_flavor = match oauth {
+            Some(_) => ApiFlavor::Cloud,
+            None => ApiFlavor::Server,
+        };
+        let flavor = v.parse("twjp.api_flavor", default_flavor, ApiFlavor::from_str);
+        if oauth.is_some() && flavor != ApiFlavor::Cloud {
+            v.errors
+                .push("twjp.oauth.client_id requires twjp.api_flavor to be cloud".to_string());
+        }
+        let report_tags = timewarrior::report_tags(tw_conf);
+        let login = report_tags.iter().any(|t| t == "login");
+        if login && oauth.is_none() {
+            v.errors
+                .push("Logging in requires twjp.oauth.client_id to be configured".to_string());
+        }
+        let api_version = v.parse("twjp.api_version", flavor.default_api_version().to_string(), |s| {
+            Ok::<_, String>(s.to_string())
+        });
+        let timeout = v.parse("twjp.timeout", Duration::from_secs(5), parse_seconds);
+        let mut retry = RetryPolicy::default();
+        retry.max_retries = v.parse("twjp.max_retries", retry.max_retries, u32::from_str);
+        retry.max_delay = v.parse("twjp.max_retry_delay", retry.max_delay, parse_seconds);
+        let max_concurrency = v.parse("twjp.max_concurrency", 4, |s| match usize::from_str(s) {
+            Ok(n) if n > 0 => Ok(n),
+            _ => Err("expected a positive number"),
+        });
+        v.parse("twjp.log_level", LevelFilter::Error, LevelFilter::from_str);
+        let skip_existing = v.parse("twjp.skip_existing", true, parse_bool);
+        let uploaded_tag = v.parse("twjp.uploaded_tag", "jira-uploaded".to_string(), |s| {
+            match s.is_empty() {
+                true => Err("must not be empty"),
+                false => Ok(s.to_string()),
+            }
+        });
+        let timezone = v.parse("twjp.timezone", Timezone::default(), Timezone::from_str);
+        // TimeWarrior passes an empty value for an open-ended range
+        let mut report_time = |key: &str| {
+            v.parse(key, None, |s| match s.is_empty() {
+                true => Ok(None),
+                false => timewarrior::parse_tw_time(s, &timezone).map(Some),
+            })
+        };
+        let range = (report_time("temp.report.start"), report_time("temp.report.end"));
+        let filter_tags: Vec<String> = report_tags
+            .iter()
+            .filter(|t| !COMMANDS.contains(&t.as_str()))
+            .cloned()
+            .collect();
+        let project_keys: Vec<String> = tw_conf
+            .get("twjp.project_keys")
+            .map(|keys| {
+                keys.split(',')
+                    .map(|k| k.trim().to_string())
+                    .filter(|k| !k.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+        let issue_key_pattern = tw_conf
+            .get("twjp.issue_key_pattern")
+            .map(|p| p.as_str())
+            .unwrap_or(jira::DEFAULT_ISSUE_KEY_PATTERN);
+        let issue_matcher = match IssueMatcher::new(issue_key_pattern, project_keys) {
+            Ok(m) => Some(m),
+            Err(e) => {
+                v.errors.push(format!(
+                    "Invalid value {:?} for twjp.issue_key_pattern: {}",
+                    issue_key_pattern, e
+                ));
+                None
+            }
+        };
+        let aggregation = v.parse("twjp.aggregate", Aggregation::None, Aggregation::from_str);
+        let default_rounding = RoundingPolicy::default();
+        let rounding = RoundingPolicy {
+            mode: v.parse("twjp.rounding.mode", default_rounding.mode, RoundingMode::from_str),
+            increment: v.parse("twjp.rounding.increment", default_rounding.increment, |s| {
+                parse_minutes(s, 1)
+            }),
+            minimum: v.parse("twjp.rounding.minimum", default_rounding.minimum, |s| {
+                parse_minutes(s, 0)
+            }),
+        };
+        let reconcile = v.parse("twjp.reconcile", false, parse_bool);
+        let delete_orphans = v.parse("twjp.delete_orphans", false, parse_bool);
+        let dry_run =
+            v.parse("twjp.dry_run", false, parse_bool) || report_tags.iter().any(|t| t == "dry-run");
+        if delete_orphans && !reconcile {
+            v.errors
+                .push("twjp.delete_orphans has no effect unless twjp.reconcile is set".to_string());
+        }
+        // Worklogs of intervals excluded by the filter would look orphaned
+        if reconcile && !filter_tags.is_empty() {
+            v.errors
+                .push("twjp.reconcile can't be combined with filtering by tags".to_string());
+        }
+
+        match (v.errors.is_empty(), issue_matcher) {
+            (true, Some(issue_matcher)) => Ok(Config {
+                url,
+                user,
+                token,
+                is_pat,
+                oauth,
+                api_version,
+                flavor,
+                timeout,
+                retry,
+                max_concurrency,
+                log_level: log_level(tw_conf),
+                skip_existing,
+                uploaded_tag,
+                timezone,
+                issue_matcher,
+                ledger: ledger::ledger_path(tw_conf),
+                aggregation,
+                rounding,
+                reconcile,
+                delete_orphans,
+                dry_run,
+                check: report_tags.iter().any(|t| t == "check"),
+                login,
+                range,
+                filter_tags,
+            }),
+            _ => Err(v.errors),
+        }
+    }
+
+    /// Builds the Jira connection information
+    pub fn jira_connection(&self) -> JiraConnection {
+        JiraConnection {
+            user: self.user.clone(),
+            token: self.token.clone(),
+            is_pat: self.is_pat,
+            instance_url: self.url.clone(),
+            api_version: self.api_version.clone(),
+            flavor: self.flavor,
+            account_id: None,
+            retry: self.retry.clone(),
+            oauth: None,
+        }
+    }
+}
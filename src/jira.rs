@@ -1,48 +1,350 @@
-use log::{debug, warn};
-use reqwest::{Client, Response, RequestBuilder};
+use crate::oauth::OAuthSession;
+use log::{debug, log_enabled, warn, Level};
+use regex::Regex;
+use reqwest::{Client, Response, RequestBuilder, StatusCode};
 use reqwest::header;
 use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use time::{OffsetDateTime, PrimitiveDateTime};
 
 /// Jira instance connection information
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub struct JiraConnection {
     pub user: String,
     pub token: String,
     pub is_pat: bool,
     pub instance_url: String,
+    /// The REST API version to use, e.g. `latest`, `2` or `3`
+    pub api_version: String,
+    pub flavor: ApiFlavor,
+    /// The accountId of the user on Jira Cloud, resolved with [`get_myself`]
+    pub account_id: Option<String>,
+    pub retry: RetryPolicy,
+    /// The OAuth session used instead of the token, if logged in with OAuth
+    pub oauth: Option<Arc<OAuthSession>>,
+}
+
+/// The kind of Jira instance, which determines how users are identified
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ApiFlavor {
+    /// Jira Server or Data Center, which identify users by `name`
+    Server,
+    /// Jira Cloud, which identifies users by `accountId` and no longer exposes `name`
+    Cloud,
+}
+
+impl FromStr for ApiFlavor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "server" | "dc" => Ok(ApiFlavor::Server),
+            "cloud" => Ok(ApiFlavor::Cloud),
+            _ => Err("expected server, dc or cloud".to_string()),
+        }
+    }
+}
+
+impl ApiFlavor {
+    /// The REST API version used unless configured otherwise
+    pub fn default_api_version(&self) -> &'static str {
+        match self {
+            ApiFlavor::Server => "latest",
+            ApiFlavor::Cloud => "3",
+        }
+    }
+}
+
+/// How requests are retried when Jira is throttling us, unavailable, or not responding
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetryPolicy {
+    /// The number of retries after the first attempt
+    pub max_retries: u32,
+    /// The delay before the first retry, doubled on each subsequent retry
+    pub base_delay: Duration,
+    /// The longest we will wait before any retry, including when told to wait by `Retry-After`
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// The jittered exponential backoff before a given retry (starting at 1).
+    /// Waits a random duration between half and all of the exponential delay,
+    /// so concurrent requests don't all retry at once.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let exp = self
+            .base_delay
+            .checked_mul(2u32.saturating_pow(retry.saturating_sub(1)))
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay);
+        let half = exp.as_millis() as u64 / 2;
+        Duration::from_millis(half + fastrand::u64(0..=half))
+    }
+}
+
+/// Parses a `Retry-After` header, which holds either a number of seconds or an HTTP date
+pub fn parse_retry_after(value: &str, now: OffsetDateTime) -> Option<Duration> {
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = PrimitiveDateTime::parse(value.trim(), "%a, %d %b %Y %H:%M:%S GMT")
+        .ok()?
+        .assume_utc();
+    Some(Duration::from_secs((date - now).whole_seconds().max(0) as u64))
+}
+
+/// Whether a response status means the request should be retried later
+fn is_retryable_status(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::TOO_MANY_REQUESTS
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+/// Sends a request, retrying on throttling, unavailability, and timeouts.
+/// A request that isn't idempotent, like creating a worklog, may have been processed even though
+/// it timed out or failed at a gateway, so it's only retried if throttled or never sent.
+async fn send(rb: RequestBuilder, jc: &JiraConnection, idempotent: bool) -> reqwest::Result<Response> {
+    if log_enabled!(Level::Debug) {
+        if let Some(Ok(req)) = rb.try_clone().map(|rb| rb.build()) {
+            debug!("req: {} {} {:?}", req.method(), req.url(), redact_headers(req.headers()));
+        }
+    }
+    let mut retry = 0;
+    loop {
+        // Our request bodies are plain strings, so requests can always be cloned
+        let result = match rb.try_clone() {
+            Some(rb) => rb.send().await,
+            None => return rb.send().await,
+        };
+        retry += 1;
+        let delay = match &result {
+            Ok(r) if r.status() == StatusCode::TOO_MANY_REQUESTS
+                || (idempotent && is_retryable_status(r.status())) => r
+                .headers()
+                .get(header::RETRY_AFTER)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| parse_retry_after(v, OffsetDateTime::now_utc()))
+                .unwrap_or_else(|| jc.retry.backoff(retry)),
+            Err(e) if e.is_connect() || (idempotent && e.is_timeout()) => jc.retry.backoff(retry),
+            _ => return result,
+        };
+        if retry > jc.retry.max_retries {
+            return result;
+        }
+        let delay = delay.min(jc.retry.max_delay);
+        match &result {
+            Ok(r) => warn!("Jira responded {}, retrying in {:?}", r.status(), delay),
+            Err(e) => warn!("Error connecting to Jira: {}, retrying in {:?}", e, delay),
+        }
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// Masks the token, as debug logs are often shared when asking for help
+impl std::fmt::Debug for JiraConnection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JiraConnection")
+            .field("user", &self.user)
+            .field("token", &"<redacted>")
+            .field("is_pat", &self.is_pat)
+            .field("instance_url", &self.instance_url)
+            .field("api_version", &self.api_version)
+            .field("flavor", &self.flavor)
+            .field("account_id", &self.account_id)
+            .field("retry", &self.retry)
+            .field("oauth", &self.oauth)
+            .finish()
+    }
+}
+
+/// Lists the headers of a request for logging, with credentials masked
+pub fn redact_headers(headers: &header::HeaderMap) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = match name {
+                &header::AUTHORIZATION | &header::PROXY_AUTHORIZATION | &header::COOKIE => {
+                    "<redacted>".to_string()
+                }
+                _ => String::from_utf8_lossy(value.as_bytes()).to_string(),
+            };
+            (name.to_string(), value)
+        })
+        .collect()
+}
+
+impl JiraConnection {
+    /// Whether the connection uses Jira Cloud's REST API v3, which expects rich text fields
+    /// in Atlassian Document Format rather than plain text.
+    pub fn uses_adf(&self) -> bool {
+        self.api_version == "3"
+    }
+
+    /// The author of our worklogs, identified as the flavor expects
+    pub fn author(&self) -> Author {
+        match self.flavor {
+            ApiFlavor::Server => Author {
+                name: Some(self.user.clone()),
+                account_id: None,
+            },
+            ApiFlavor::Cloud => Author {
+                name: None,
+                account_id: self.account_id.clone(),
+            },
+        }
+    }
+
+    /// Whether a worklog author is the user we're connected as
+    pub fn is_author(&self, author: &Author) -> bool {
+        match self.flavor {
+            ApiFlavor::Server => author.name.as_ref() == Some(&self.user),
+            ApiFlavor::Cloud => author.account_id.is_some() && author.account_id == self.account_id,
+        }
+    }
+}
+
+/// The format of worklog start times sent to Jira
+pub const WORKLOG_TIME_FORMAT: &str = "%FT%H:%M:%S.000%z";
+
+/// The default pattern used to recognize bare issue keys in tags, such as `PROJ-123`
+pub const DEFAULT_ISSUE_KEY_PATTERN: &str = r"[A-Z][A-Z0-9_]+-\d+";
+
+/// Finds Jira issues referenced by TimeWarrior tags.
+/// Tags may either contain a full browse URL, or consist of a bare issue key.
+#[derive(Clone, Debug)]
+pub struct IssueMatcher {
+    url_re: Regex,
+    key_re: Regex,
+    project_keys: Vec<String>,
+}
+
+impl IssueMatcher {
+    /// Creates a matcher recognizing bare keys matching `key_pattern`.
+    /// If `project_keys` is not empty, bare keys are only accepted for those projects.
+    pub fn new(key_pattern: &str, project_keys: Vec<String>) -> Result<Self, regex::Error> {
+        Ok(IssueMatcher {
+            url_re: Regex::new(r"(?P<url>https?://.+browse/(?P<issue>.+))")?,
+            key_re: Regex::new(&format!("^(?:{})$", key_pattern))?,
+            project_keys,
+        })
+    }
+
+    /// Returns the issue key for the first tag containing a Jira-esque URL,
+    /// or failing that, the first tag that is a bare issue key.
+    pub fn find_issue(&self, tags: &[String]) -> Option<String> {
+        if let Some(c) = tags.iter().find_map(|t| self.url_re.captures(t)) {
+            return Some(c["issue"].to_string());
+        }
+        tags.iter()
+            .find(|t| self.key_re.is_match(t) && self.is_allowed_project(t))
+            .cloned()
+    }
+
+    /// Checks a bare issue key against the project allow-list
+    fn is_allowed_project(&self, key: &str) -> bool {
+        if self.project_keys.is_empty() {
+            return true;
+        }
+        match key.rsplit_once('-') {
+            Some((project, _)) => self.project_keys.iter().any(|p| p == project),
+            None => false,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Author {
-    pub name: String,
+    /// The username, only available on Jira Server and Data Center
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// The accountId, only available on Jira Cloud
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub account_id: Option<String>,
+}
+
+/// A work log comment, which is plain text for REST API v2 and an
+/// [Atlassian Document Format](https://developer.atlassian.com/cloud/jira/platform/apis/document/structure/)
+/// document for REST API v3.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum WorklogComment {
+    Text(String),
+    Document(serde_json::Value),
+}
+
+impl WorklogComment {
+    /// Builds a comment from plain text, in the format expected by the given connection.
+    /// Each non-empty line of the text becomes its own paragraph in ADF documents.
+    pub fn new(jc: &JiraConnection, text: &str) -> Self {
+        if !jc.uses_adf() {
+            return WorklogComment::Text(text.to_string());
+        }
+        let paragraphs: Vec<serde_json::Value> = text
+            .lines()
+            .filter(|l| !l.trim().is_empty())
+            .map(|l| {
+                serde_json::json!({
+                    "type": "paragraph",
+                    "content": [{ "type": "text", "text": l }],
+                })
+            })
+            .collect();
+        WorklogComment::Document(serde_json::json!({
+            "type": "doc",
+            "version": 1,
+            "content": paragraphs,
+        }))
+    }
 }
 
 /// A structure representing a Jira work log.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct JiraWorklog {
+    /// The ID assigned by Jira, absent for worklogs not yet uploaded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
     pub started: String,
     pub time_spent_seconds: i64,
     pub author: Author,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<WorklogComment>,
 }
 
-fn add_common_headers(
-    rc: RequestBuilder,
-    jc: &JiraConnection
-) -> reqwest::RequestBuilder {
-    let mut tmp = rc
+async fn add_common_headers(
+    rb: RequestBuilder,
+    rc: &Client,
+    jc: &JiraConnection,
+) -> Result<RequestBuilder, JiraError> {
+    let mut tmp = rb
         .header(header::ACCEPT, header::HeaderValue::from_static("application/json"))
         .header(header::USER_AGENT, header::HeaderValue::from_static("jirapush/0.1.0"));
-    if jc.is_pat {
-        //tmp = tmp.bearer_auth(&jc.token);
-        tmp = tmp.header(header::AUTHORIZATION, format!("Bearer {}", &jc.token));
+    if let Some(oauth) = &jc.oauth {
+        tmp = tmp.bearer_auth(oauth.access_token(rc).await?);
+    } else if jc.is_pat {
+        tmp = tmp.bearer_auth(&jc.token);
     } else {
         tmp = tmp.basic_auth(&jc.user, Some(&jc.token));
     }
     debug!("jc: {:#?}", &jc);
-    debug!("req: {:#?}", &tmp);
-    tmp
+    Ok(tmp)
 }
 
 /// Generic get function for Jira API
@@ -51,15 +353,15 @@ async fn get(
     jc: &JiraConnection,
     endpoint: &str,
     query: &Vec<(String, String)>,
-) -> reqwest::Result<Response> {
-    add_common_headers(rc.get(format!(
+) -> Result<Response, JiraError> {
+    let rb = add_common_headers(rc.get(format!(
         "{base_url}/{endpoint}",
         base_url = jc.instance_url,
         endpoint = endpoint
-    )), &jc)
-    .query(query)
-    .send()
-    .await
+    )), rc, jc)
+    .await?
+    .query(query);
+    Ok(send(rb, jc, true).await?)
 }
 
 /// Generic post function for Jira API
@@ -68,111 +370,406 @@ async fn post(
     jc: &JiraConnection,
     endpoint: &str,
     body: String,
-) -> reqwest::Result<Response> {
-    add_common_headers(rc.post(format!(
+) -> Result<Response, JiraError> {
+    let rb = add_common_headers(rc.post(format!(
         "{base_url}/{endpoint}",
         base_url = jc.instance_url,
         endpoint = endpoint
-    )), &jc)
+    )), rc, jc)
+    .await?
     .header("Content-Type", "application/json")
-    .body(body)
-    .send()
-    .await
+    .body(body);
+    Ok(send(rb, jc, false).await?)
 }
 
+/// Generic put function for Jira API
+async fn put(
+    rc: &Client,
+    jc: &JiraConnection,
+    endpoint: &str,
+    body: String,
+) -> Result<Response, JiraError> {
+    let rb = add_common_headers(rc.put(format!(
+        "{base_url}/{endpoint}",
+        base_url = jc.instance_url,
+        endpoint = endpoint
+    )), rc, jc)
+    .await?
+    .header("Content-Type", "application/json")
+    .body(body);
+    Ok(send(rb, jc, true).await?)
+}
+
+/// Generic delete function for Jira API
+async fn delete(rc: &Client, jc: &JiraConnection, endpoint: &str) -> Result<Response, JiraError> {
+    let rb = add_common_headers(rc.delete(format!(
+        "{base_url}/{endpoint}",
+        base_url = jc.instance_url,
+        endpoint = endpoint
+    )), rc, jc)
+    .await?;
+    Ok(send(rb, jc, true).await?)
+}
+
+/// Worklogs under 60 seconds are not recognized by JIRA, we need to round up
+fn round_up_short_worklog(wl: &JiraWorklog) -> JiraWorklog {
+    let mut temp_wl = wl.clone();
+    if temp_wl.time_spent_seconds < 60 {
+        temp_wl.time_spent_seconds = 60;
+    }
+    temp_wl
+}
+
+/// Errors returned by Jira API calls
+#[derive(Debug)]
+pub enum JiraError {
+    /// The request couldn't be sent, or no response was received
+    Connection(reqwest::Error),
+    /// The credentials were rejected (401), or login was denied, e.g. pending a CAPTCHA after failed logins (403)
+    Authentication(StatusCode),
+    /// We lack permission for this issue or worklog, e.g. to log work in its project (403)
+    Forbidden,
+    /// The issue or worklog doesn't exist, or isn't visible to us (404)
+    NotFound,
+    /// Jira was still throttling us after all retries (429)
+    RateLimited,
+    /// Jira responded with any other unsuccessful status
+    Status(StatusCode, String),
+    /// The response couldn't be read or parsed
+    Parse(String),
+}
+
+impl std::fmt::Display for JiraError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JiraError::Connection(e) => write!(f, "Connection error: {}", e),
+            JiraError::Authentication(status) => {
+                write!(
+                    f,
+                    "Authentication failed ({}), check your user and token",
+                    status
+                )
+            }
+            JiraError::Forbidden => write!(f, "Permission denied"),
+            JiraError::NotFound => write!(f, "Not found"),
+            JiraError::RateLimited => write!(f, "Rate limited by Jira"),
+            JiraError::Status(status, body) => {
+                write!(f, "Unexpected status {}: {:?}", status, body)
+            }
+            JiraError::Parse(e) => write!(f, "Error parsing response: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for JiraError {}
+
+impl From<reqwest::Error> for JiraError {
+    fn from(e: reqwest::Error) -> Self {
+        JiraError::Connection(e)
+    }
+}
+
+/// Set by Jira Server on a 403 when it denies the login, e.g. pending a CAPTCHA
+const AUTH_DENIED_HEADER: &str = "X-Authentication-Denied-Reason";
+
+/// Maps unsuccessful responses to errors
+async fn check_status(r: Response) -> Result<Response, JiraError> {
+    match r.status() {
+        s if s.is_success() => Ok(r),
+        StatusCode::UNAUTHORIZED => Err(JiraError::Authentication(r.status())),
+        StatusCode::FORBIDDEN if r.headers().contains_key(AUTH_DENIED_HEADER) => {
+            Err(JiraError::Authentication(r.status()))
+        }
+        StatusCode::FORBIDDEN => Err(JiraError::Forbidden),
+        StatusCode::NOT_FOUND => Err(JiraError::NotFound),
+        StatusCode::TOO_MANY_REQUESTS => Err(JiraError::RateLimited),
+        s => Err(JiraError::Status(s, r.text().await.unwrap_or_default())),
+    }
+}
+
+/// Reads and deserializes a successful response body
+async fn parse_body<T: serde::de::DeserializeOwned>(r: Response) -> Result<T, JiraError> {
+    let body = r
+        .text()
+        .await
+        .map_err(|e| JiraError::Parse(e.to_string()))?;
+    serde_json::from_str(&body).map_err(|e| {
+        debug!("Body: {body}", body = &body);
+        JiraError::Parse(e.to_string())
+    })
+}
+
+/// A page of worklogs returned by Jira
 #[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 struct JiraResponseWorklog {
+    #[serde(default)]
+    start_at: usize,
+    #[serde(default)]
+    total: usize,
     worklogs: Vec<JiraWorklog>,
 }
 
-/// Pulls existing worklogs from Jira for a given issue.
-pub async fn get_worklogs(rc: &Client, jc: &JiraConnection, issue: &str) -> Vec<JiraWorklog> {
-    // Fetch worklogs
-    match get(
-        rc,
-        jc,
-        &format!("rest/api/latest/issue/{issue}/worklog", issue = issue),
-        &vec![],
-    )
-    .await
-    {
-        // Handle failed connections
-        Err(e) => {
-            warn!(
-                "Connection error fetching worklogs for {issue}: {error}",
-                issue = issue,
-                error = e
-            );
-            vec![]
-        }
-        // On successful connection, ensure success
-        Ok(r) => match r.status().is_success() {
-            false => {
-                warn!(
-                    "Error fetching worklogs for {issue}: {status}",
-                    issue = issue,
-                    status = r.status()
-                );
-                vec![]
-            }
-            // On successful fetch, move on
-            true => {
-                let body = &r.text().await.unwrap().clone();
-                let jr: JiraResponseWorklog = match serde_json::from_str(&body) {
-                    Err(e) => {
-                        warn!(
-                            "Error parsing worklogs for {issue}: {error}",
-                            issue = issue,
-                            error = e
-                        );
-                        debug!("Body: {body}", body = &body);
-                        JiraResponseWorklog { worklogs: vec![] }
-                    }
-                    Ok(r) => r,
-                };
-                jr.worklogs
-            }
-        },
+/// Pulls existing worklogs from Jira for a given issue, following pagination until all are collected.
+/// If `started_after` is given, only worklogs started after it are requested.
+pub async fn get_worklogs(
+    rc: &Client,
+    jc: &JiraConnection,
+    issue: &str,
+    started_after: Option<OffsetDateTime>,
+) -> Result<Vec<JiraWorklog>, JiraError> {
+    let mut worklogs = Vec::new();
+    loop {
+        let mut query = vec![("startAt".to_string(), worklogs.len().to_string())];
+        if let Some(started_after) = started_after {
+            // Jira expects a UNIX timestamp in milliseconds
+            query.push((
+                "startedAfter".to_string(),
+                (started_after.unix_timestamp() * 1000).to_string(),
+            ));
+        }
+        let r = get(
+            rc,
+            jc,
+            &format!(
+                "rest/api/{version}/issue/{issue}/worklog",
+                version = jc.api_version,
+                issue = issue
+            ),
+            &query,
+        )
+        .await?;
+        let page: JiraResponseWorklog = parse_body(check_status(r).await?).await?;
+        debug!(
+            "Fetched worklogs {}-{} of {} for {}",
+            page.start_at,
+            page.start_at + page.worklogs.len(),
+            page.total,
+            issue
+        );
+        let page_len = page.worklogs.len();
+        worklogs.extend(page.worklogs);
+        if page_len == 0 || worklogs.len() >= page.total {
+            return Ok(worklogs);
+        }
     }
 }
 
+/// The parts of Jira's response to a worklog upload we're interested in
+#[derive(Deserialize)]
+struct JiraResponseCreatedWorklog {
+    id: String,
+}
+
 /// Uploads a worklog to Jira.
+/// Returns the ID Jira assigned to the new worklog.
 pub async fn upload_worklog(
     rc: &Client,
     jc: &JiraConnection,
     issue: &str,
     wl: &JiraWorklog,
-) -> Result<(), String> {
-    let mut temp_wl = wl.clone();
-    // Worklogs under 60 seconds are not recognized by JIRA, we need to round up
-    if temp_wl.time_spent_seconds < 60 {
-        temp_wl.time_spent_seconds = 60;
-    }
-    // Fetch worklogs
-    match post(
+) -> Result<String, JiraError> {
+    let temp_wl = round_up_short_worklog(wl);
+    // Upload worklog
+    let r = post(
         rc,
         jc,
-        &format!("rest/api/latest/issue/{issue}/worklog", issue = issue),
+        &format!(
+            "rest/api/{version}/issue/{issue}/worklog",
+            version = jc.api_version,
+            issue = issue
+        ),
         serde_json::to_string(&temp_wl).unwrap(),
     )
-    .await
-    {
-        // Handle failed connections
-        Err(e) => Err(format!(
-            "Connection error uploading worklog for {issue}: {error}",
+    .await?;
+    // On successful upload, read back the new worklog's ID
+    let created: JiraResponseCreatedWorklog = parse_body(check_status(r).await?).await?;
+    Ok(created.id)
+}
+
+/// Updates an existing Jira worklog, e.g. after its interval was edited locally.
+pub async fn update_worklog(
+    rc: &Client,
+    jc: &JiraConnection,
+    issue: &str,
+    id: &str,
+    wl: &JiraWorklog,
+) -> Result<(), JiraError> {
+    let temp_wl = round_up_short_worklog(wl);
+    let r = put(
+        rc,
+        jc,
+        &format!(
+            "rest/api/{version}/issue/{issue}/worklog/{id}",
+            version = jc.api_version,
+            issue = issue,
+            id = id
+        ),
+        serde_json::to_string(&temp_wl).unwrap(),
+    )
+    .await?;
+    check_status(r).await?;
+    Ok(())
+}
+
+/// Deletes a Jira worklog, e.g. after its interval was deleted locally.
+pub async fn delete_worklog(
+    rc: &Client,
+    jc: &JiraConnection,
+    issue: &str,
+    id: &str,
+) -> Result<(), JiraError> {
+    let r = delete(
+        rc,
+        jc,
+        &format!(
+            "rest/api/{version}/issue/{issue}/worklog/{id}",
+            version = jc.api_version,
             issue = issue,
-            error = e
-        )),
-        // On successful connection, check status code
-        Ok(r) => match r.status().is_success() {
-            false => Err(format!(
-                "Error submitting worklog for {issue}: {status}\n{body:?}",
-                issue = issue,
-                status = r.status(),
-                body = r.text().await.unwrap()
-            )),
-            // On successful fetch, move on
-            true => Ok(()),
-        },
+            id = id
+        ),
+    )
+    .await?;
+    check_status(r).await?;
+    Ok(())
+}
+
+/// The Jira user we're authenticated as.
+/// Jira Server and Data Center identify users by `name`, Jira Cloud by `accountId`.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct JiraUser {
+    pub name: Option<String>,
+    pub account_id: Option<String>,
+    pub display_name: Option<String>,
+}
+
+/// Information about the Jira instance
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerInfo {
+    pub version: String,
+    /// `Cloud` or `Server`, where `Server` includes Data Center
+    pub deployment_type: Option<String>,
+}
+
+/// Fetches the user our credentials belong to
+pub async fn get_myself(rc: &Client, jc: &JiraConnection) -> Result<JiraUser, JiraError> {
+    let r = get(rc, jc, "rest/api/latest/myself", &vec![]).await?;
+    parse_body(check_status(r).await?).await
+}
+
+/// Fetches the version and deployment type of the Jira instance
+pub async fn get_server_info(rc: &Client, jc: &JiraConnection) -> Result<ServerInfo, JiraError> {
+    let r = get(rc, jc, "rest/api/latest/serverInfo", &vec![]).await?;
+    parse_body(check_status(r).await?).await
+}
+
+/// Checks that Jira is reachable and accepts our credentials, printing the results.
+/// Returns whether the check succeeded.
+pub async fn check_connection(rc: &Client, jc: &JiraConnection) -> bool {
+    let auth = match (&jc.oauth, jc.is_pat) {
+        (Some(_), _) => "OAuth",
+        (None, true) => "personal access token",
+        (None, false) => "basic auth",
+    };
+    println!("Jira:   {}", jc.instance_url);
+    match get_myself(rc, jc).await {
+        Ok(user) => {
+            let id = match (&user.account_id, &user.name) {
+                (Some(account_id), _) => format!("accountId {}", account_id),
+                (None, Some(name)) => format!("name {}", name),
+                (None, None) => "unknown id".to_string(),
+            };
+            println!(
+                "Auth:   {} succeeded, authenticated as {} ({})",
+                auth,
+                user.display_name.as_deref().unwrap_or(&jc.user),
+                id
+            );
+        }
+        Err(e) => {
+            println!("Auth:   {} failed: {}", auth, e);
+            return false;
+        }
+    }
+    match get_server_info(rc, jc).await {
+        Ok(info) => {
+            println!(
+                "Server: Jira {} {}",
+                info.deployment_type.as_deref().unwrap_or(""),
+                info.version
+            );
+            true
+        }
+        Err(e) => {
+            println!("Server: error fetching server info: {}", e);
+            false
+        }
+    }
+}
+
+/// The Jira operations needed to sync worklogs, so the sync can be run against another client,
+/// such as a fake in tests
+pub trait JiraClient {
+    /// The connection information, which determines how worklogs are authored and formatted
+    fn connection(&self) -> &JiraConnection;
+
+    fn get_worklogs(
+        &self,
+        issue: &str,
+        started_after: Option<OffsetDateTime>,
+    ) -> impl Future<Output = Result<Vec<JiraWorklog>, JiraError>> + Send;
+
+    fn upload_worklog(
+        &self,
+        issue: &str,
+        wl: &JiraWorklog,
+    ) -> impl Future<Output = Result<String, JiraError>> + Send;
+
+    fn update_worklog(
+        &self,
+        issue: &str,
+        id: &str,
+        wl: &JiraWorklog,
+    ) -> impl Future<Output = Result<(), JiraError>> + Send;
+
+    fn delete_worklog(
+        &self,
+        issue: &str,
+        id: &str,
+    ) -> impl Future<Output = Result<(), JiraError>> + Send;
+}
+
+/// A client for the Jira REST API
+#[derive(Debug, Clone)]
+pub struct RestClient {
+    pub rc: Client,
+    pub jc: JiraConnection,
+}
+
+impl JiraClient for RestClient {
+    fn connection(&self) -> &JiraConnection {
+        &self.jc
+    }
+
+    async fn get_worklogs(
+        &self,
+        issue: &str,
+        started_after: Option<OffsetDateTime>,
+    ) -> Result<Vec<JiraWorklog>, JiraError> {
+        get_worklogs(&self.rc, &self.jc, issue, started_after).await
+    }
+
+    async fn upload_worklog(&self, issue: &str, wl: &JiraWorklog) -> Result<String, JiraError> {
+        upload_worklog(&self.rc, &self.jc, issue, wl).await
+    }
+
+    async fn update_worklog(&self, issue: &str, id: &str, wl: &JiraWorklog) -> Result<(), JiraError> {
+        update_worklog(&self.rc, &self.jc, issue, id, wl).await
+    }
+
+    async fn delete_worklog(&self, issue: &str, id: &str) -> Result<(), JiraError> {
+        delete_worklog(&self.rc, &self.jc, issue, id).await
     }
 }
@@ -0,0 +1,124 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// The file name of the ledger within the TimeWarrior data directory
+const LEDGER_FILE: &str = "jirapush.json";
+
+/// A record of a single interval uploaded to Jira
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LedgerEntry {
+    pub start: String,
+    pub end: String,
+    pub issue: String,
+    pub worklog_id: String,
+}
+
+impl LedgerEntry {
+    /// Whether this records the upload of the given interval, either as it is
+    /// or before an edit changing one of its ends (see `Ledger::find_edited`)
+    pub fn matches(&self, start: &str, end: &str, issue: &str) -> bool {
+        self.issue == issue && (self.start == start || self.end == end)
+    }
+}
+
+/// A persistent local record of uploaded intervals.
+/// This is the source of truth for what has been uploaded; the TimeWarrior tag is only a convenience.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Ledger {
+    #[serde(skip)]
+    path: PathBuf,
+    entries: Vec<LedgerEntry>,
+}
+
+/// Determines the TimeWarrior database directory, where we keep our own files by default
+pub fn data_dir(tw_conf: &HashMap<String, String>) -> PathBuf {
+    match tw_conf.get("temp.db") {
+        Some(db) => PathBuf::from(db),
+        None => match std::env::var("TIMEWARRIORDB") {
+            Ok(db) => PathBuf::from(db),
+            Err(_) => PathBuf::from(std::env::var("HOME").unwrap_or_default()).join(".timewarrior"),
+        },
+    }
+}
+
+/// Determines where the ledger is stored.
+/// Uses `twjp.ledger` if configured, otherwise the TimeWarrior database directory.
+pub fn ledger_path(tw_conf: &HashMap<String, String>) -> PathBuf {
+    if let Some(path) = tw_conf.get("twjp.ledger") {
+        return PathBuf::from(path);
+    }
+    data_dir(tw_conf).join(LEDGER_FILE)
+}
+
+impl Ledger {
+    /// Loads the ledger at a given path. A missing file is treated as an empty ledger.
+    pub fn load(path: &Path) -> Result<Ledger, String> {
+        let mut ledger: Ledger = match std::fs::read_to_string(path) {
+            Ok(body) => serde_json::from_str(&body)
+                .map_err(|e| format!("Error parsing ledger {}: {}", path.display(), e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ledger::default(),
+            Err(e) => return Err(format!("Error reading ledger {}: {}", path.display(), e)),
+        };
+        ledger.path = path.to_path_buf();
+        Ok(ledger)
+    }
+
+    /// Writes the ledger back to disk.
+    /// The file is replaced atomically so a crash cannot leave a truncated ledger behind.
+    pub fn save(&self) -> Result<(), String> {
+        let body = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Error serializing ledger: {}", e))?;
+        let tmp_path = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp_path, body)
+            .and_then(|_| std::fs::rename(&tmp_path, &self.path))
+            .map_err(|e| format!("Error writing ledger {}: {}", self.path.display(), e))
+    }
+
+    /// Finds the record of an interval's upload, if any
+    pub fn find(&self, start: &str, end: &str, issue: &str) -> Option<&LedgerEntry> {
+        self.entries
+            .iter()
+            .find(|e| e.start == start && e.end == end && e.issue == issue)
+    }
+
+    /// Finds the record of an interval that has since been edited locally.
+    /// An edit such as `timew lengthen` or `timew modify start` changes only one end of an interval,
+    /// so a record for the same issue sharing exactly one of the start or end time is a match.
+    /// Edits changing both ends, such as `timew move`, can't be told apart from other intervals.
+    pub fn find_edited(&self, start: &str, end: &str, issue: &str) -> Option<&LedgerEntry> {
+        self.entries
+            .iter()
+            .find(|e| e.issue == issue && ((e.start == start) != (e.end == end)))
+    }
+
+    /// All records, in the order they were made
+    pub fn entries(&self) -> &[LedgerEntry] {
+        &self.entries
+    }
+
+    /// Finds the records of all intervals contributing to a worklog
+    pub fn find_worklog(&self, worklog_id: &str) -> Vec<&LedgerEntry> {
+        self.entries
+            .iter()
+            .filter(|e| e.worklog_id == worklog_id)
+            .collect()
+    }
+
+    /// Records an upload, replacing any existing record of the same interval
+    pub fn record(&mut self, entry: LedgerEntry) {
+        self.remove(&entry);
+        self.entries.push(entry);
+    }
+
+    /// Removes the record of an interval, e.g. when it was edited
+    pub fn remove(&mut self, entry: &LedgerEntry) {
+        self.entries
+            .retain(|e| !(e.start == entry.start && e.end == entry.end && e.issue == entry.issue));
+    }
+
+    /// Removes the record of a worklog, e.g. after it was deleted from Jira
+    pub fn forget(&mut self, worklog_id: &str) {
+        self.entries.retain(|e| e.worklog_id != worklog_id);
+    }
+}
@@ -0,0 +1,11 @@
+#[cfg(test)]
+pub(crate) mod tests;
+
+pub mod config;
+pub mod jira;
+pub mod ledger;
+pub mod oauth;
+pub mod rounding;
+pub mod sync;
+pub mod timewarrior;
+pub mod timezone;
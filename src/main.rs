@@ -1,16 +1,12 @@
-#[cfg(test)]
-pub(crate) mod tests;
-
-pub mod jira;
-pub mod timewarrior;
-
-use jira::JiraWorklog;
-use jira::Author;
-use log::{debug, error, info, warn, LevelFilter};
-use regex::{Captures, Regex};
-use std::{io::stdin, io::Read, str::FromStr, time::Duration};
-use time::OffsetDateTime;
-use timewarrior::TimeWarriorLog;
+use log::{error, LevelFilter};
+use std::sync::{Arc, Mutex};
+use std::{io::stdin, io::Read};
+use timewarrior_jirapush::config::{self, Config};
+use timewarrior_jirapush::jira::{self, RestClient};
+use timewarrior_jirapush::ledger::Ledger;
+use timewarrior_jirapush::oauth;
+use timewarrior_jirapush::sync;
+use timewarrior_jirapush::timewarrior::{self, TimewTagger};
 
 #[tokio::main]
 pub async fn main() {
@@ -22,179 +18,89 @@ pub async fn main() {
     let (tw_conf, tw_logs) =
         timewarrior::parse_tw_input(&input).expect("Error parsing TimeWarrior input");
 
-    // Check required config (JIRA base URL, username, token)
-    if !(tw_conf.contains_key("twjp.url")
-        && tw_conf.contains_key("twjp.user")
-        && tw_conf.contains_key("twjp.token"))
-    {
-        error!("Missing required config, please ensure twjp.url, twjp.user, and twjp.token are specified in your timewarrior config.");
-        return;
-    }
-
-    // Handle "well-behaved" config guidelines
-    // https://timewarrior.net/docs/api/#guidelines
-    let mut log_level = LevelFilter::Error;
-    if let Some(val) = tw_conf.get("verbose") {
-        match val.as_str() {
-            "on" | "1" | "yes" | "y" | "true" => log_level = LevelFilter::Warn,
-            _ => {}
-        }
-    }
-    if let Some(val) = tw_conf.get("debug") {
-        match val.as_str() {
-            "on" | "1" | "yes" | "y" | "true" => log_level = LevelFilter::Debug,
-            _ => {}
-        }
-    }
-
-    // Set log level, if specified
-    if let Some(val) = tw_conf.get("twjp.log_level") {
-        match LevelFilter::from_str(val) {
-            Ok(l) => log_level = l,
-            Err(_) => warn!("Invalid log level {}", val),
+    // Validate config before doing anything else
+    let config = match Config::from_tw_config(&tw_conf) {
+        Ok(c) => c,
+        Err(errors) => {
+            // Make sure the problems are reported, even if the log level is invalid
+            env_logger::builder()
+                .filter_level(config::log_level(&tw_conf).max(LevelFilter::Error))
+                .init();
+            for e in errors {
+                error!("{}", e);
+            }
+            error!("Please fix the twjp configuration in your timewarrior config.");
+            return;
         }
     };
 
     // Build logger
-    env_logger::builder().filter_level(log_level).init();
+    env_logger::builder().filter_level(config.log_level).init();
 
-    // Iterate over logs to determine work we need to do
-    let upload_tag = tw_conf
-        .get("twjp.uploaded_tag")
-        .unwrap_or(&"jira-uploaded".to_string())
-        .clone();
-    let is_pat = bool::from_str(
-                tw_conf
-                    .get("twjp.is_pat")
-                    .unwrap_or(&"false".to_string()),
-            )
-            .unwrap_or(false);
-    let mut pending_logs = Vec::<(String, TimeWarriorLog)>::new();
-    for tw_log in tw_logs {
-        // Check if log is uploaded, and if not, if it's complete and so needs to be
-        let is_uploaded = tw_log.tags.contains(&upload_tag);
-        let is_complete = tw_log.end.is_some();
-        if !is_uploaded && is_complete {
-            // Find the first tag containing a Jira-esque URL
-            let url_re = Regex::new(r"(?P<url>https?://.+browse/(?P<issue>.+))").unwrap();
-            let url_tags = tw_log.tags.iter().filter_map(|t| url_re.captures(t));
-            if let Some(url_captures) = url_tags.collect::<Vec<Captures>>().first() {
-                pending_logs.push((url_captures["issue"].to_string(), tw_log));
+    // Build connection info
+    let rest_c = reqwest::Client::builder()
+        .timeout(config.timeout)
+        .build()
+        .unwrap();
+    let mut jc = config.jira_connection();
+    // With OAuth, requests are sent through Atlassian's API gateway using the stored session
+    if let Some(oauth_config) = &config.oauth {
+        if config.login {
+            match oauth::login(&rest_c, oauth_config, &config.url).await {
+                Ok(_) => println!("Logged in to {}", config.url),
+                Err(e) => {
+                    error!("{}", e);
+                    std::process::exit(1);
+                }
             }
+            return;
         }
-    }
-
-    // If we have pending logs, construct a REST client and POST them
-    // Additionally, mark uploaded logs as such
-    if pending_logs.len() > 0 {
-        // Build connection info
-        let rest_c = reqwest::Client::builder()
-            .timeout(Duration::from_secs(5))
-            .build()
-            .unwrap();
-        let jc = jira::JiraConnection {
-            user: tw_conf["twjp.user"].clone(),
-            token: tw_conf["twjp.token"].clone(),
-            is_pat: is_pat,
-            instance_url: tw_conf["twjp.url"].clone(),
+        let tokens = match oauth::OAuthTokens::load(&oauth_config.token_path) {
+            Ok(t) => t,
+            Err(e) => {
+                error!("{}", e);
+                return;
+            }
         };
-
-        // Handle our pending logs
-        let mut upload_tasks = Vec::new();
-        for (issue, log) in pending_logs {
-            // Parse sparse ISO8601 dates handed off by TimeWarrior
-            let start = OffsetDateTime::parse(
-                log.start.to_string()
-                    + tw_conf.get("twjp.timezone").unwrap_or(&"+0000".to_string()),
-                "%Y%m%dT%H%M%SZ%z",
-            )
-            .unwrap();
-            let end = OffsetDateTime::parse(
-                log.end.as_ref().unwrap().clone()
-                    + tw_conf.get("twjp.timezone").unwrap_or(&"+0000".to_string()),
-                "%Y%m%dT%H%M%SZ%z",
-            )
-            .unwrap();
-
-            // Construct a compatible Jira worklog
-            let worklog = JiraWorklog {
-                started: start.format("%FT%T.000%z"),
-                time_spent_seconds: (end - start).whole_seconds(),
-                author: Author {
-                    name: tw_conf["twjp.user"].clone()
-                },
-            };
-
-            let check_existing = bool::from_str(
-                tw_conf
-                    .get("twjp.skip_existing")
-                    .unwrap_or(&"true".to_string()),
-            )
-            .unwrap_or(true);
-
-            // Spawn a thread to check and upload the log
-            let rest_c = rest_c.clone();
-            let jc = jc.clone();
-            let upload_tag = upload_tag.clone();
-            upload_tasks.push(tokio::spawn(async move {
-                // Check to see if an existing worklog at that time exists (unless configured otherwise)
-                if check_existing {
-                    // Fetch existing logs
-                    let existing_logs = jira::get_worklogs(&rest_c, &jc, &issue).await;
-                    debug!("Existing logs: {:?}", existing_logs);
-                    // Compare logs
-                    let mut exists = false;
-                    for wl in existing_logs {
-                        // Jira stores milliseconds which cannot be easily parsed here as there's no formatting directive
-                        // We will superimpose 0's there so we can still parse.
-                        let mut corrected_time = wl.started.clone();
-                        corrected_time.replace_range(20..=22, "000");
-                        let e_start =
-                            OffsetDateTime::parse(corrected_time, "%Y-%m-%dT%H:%M:%S.000%z")
-                                .unwrap();
-                        if e_start == start && wl.author.name == worklog.author.name {
-                            exists = true;
-                            break;
-                        }
-                    }
-                    // We have a log here already, skip this one.
-                    if exists {
-                        // Tag the interval as uploaded
-                        match timewarrior::tag_tw_log(&log, &upload_tag) {
-                            Ok(_) => {
-                                info!("Log already exists for {}, marking as uploaded.", issue);
-                            }
-                            Err(e) => {
-                                warn!(
-                                    "Error marking existing interval {:?} as uploaded: {}",
-                                    log, e
-                                );
-                            }
-                        }
-                        return;
-                    }
-                }
-                // Upload
-                match jira::upload_worklog(&rest_c, &jc, &issue, &worklog).await {
-                    Ok(_) => {
-                        // Tag the interval as uploaded
-                        match timewarrior::tag_tw_log(&log, &upload_tag) {
-                            Ok(_) => {
-                                info!("Logged for {}", issue);
-                            }
-                            Err(e) => {
-                                warn!("Error marking interval {:?} as uploaded: {}", log, e);
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        warn!("Error logging {:?} for {}: {}", worklog, issue, e);
-                    }
-                }
-            }));
+        jc.instance_url = tokens.api_base_url();
+        jc.oauth = Some(Arc::new(oauth::OAuthSession::new(oauth_config.clone(), tokens)));
+    }
+    if config.check {
+        if !jira::check_connection(&rest_c, &jc).await {
+            std::process::exit(1);
         }
+        return;
+    }
+    // Jira Cloud identifies worklog authors by accountId, which we have to look up
+    if jc.flavor == jira::ApiFlavor::Cloud {
+        match jira::get_myself(&rest_c, &jc).await {
+            Ok(user) if user.account_id.is_some() => jc.account_id = user.account_id,
+            Ok(_) => {
+                error!("Jira didn't return an accountId for {}, is twjp.api_flavor correct?", jc.user);
+                return;
+            }
+            Err(e) => {
+                error!("Error looking up the accountId of {}: {}", jc.user, e);
+                return;
+            }
+        }
+    }
 
-        // Join up with upload tasks
-        futures::future::join_all(upload_tasks).await;
+    // Load the ledger of previous uploads
+    let ledger = match Ledger::load(&config.ledger) {
+        Ok(l) => Mutex::new(l),
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+    let client = RestClient { rc: rest_c, jc };
+    let mut outcome = sync::run(&client, &TimewTagger::default(), &config, tw_logs, &ledger).await;
+    for adjustment in &outcome.adjustments {
+        println!("{}", adjustment);
+    }
+    // In a dry run, we only report what would happen without touching Jira or TimeWarrior
+    if config.dry_run || config.reconcile {
+        sync::print_report(&mut outcome.report);
     }
 }
@@ -0,0 +1,345 @@
+use crate::jira::JiraError;
+use log::{debug, error, info};
+use reqwest::{Client, StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use time::OffsetDateTime;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::Mutex;
+
+/// The file name of the stored OAuth tokens within the TimeWarrior data directory
+pub const TOKEN_FILE: &str = "jirapush-oauth.json";
+
+const AUTHORIZE_URL: &str = "https://auth.atlassian.com/authorize";
+/// Atlassian's token endpoint, which authorization codes and refresh tokens are exchanged at
+pub const TOKEN_URL: &str = "https://auth.atlassian.com/oauth/token";
+const RESOURCES_URL: &str = "https://api.atlassian.com/oauth/token/accessible-resources";
+/// The scopes we need to read and write worklogs, and `offline_access` for a refresh token
+const SCOPES: &str = "read:jira-work write:jira-work read:jira-user offline_access";
+/// How long before expiry an access token is refreshed, in seconds
+const EXPIRY_MARGIN: i64 = 60;
+
+/// The OAuth 2.0 app registered in the Atlassian developer console
+#[derive(Clone)]
+pub struct OAuthConfig {
+    pub client_id: String,
+    pub client_secret: String,
+    /// The port of the loopback redirect listener, which must match the app's callback URL
+    pub port: u16,
+    /// Where the tokens are stored between runs
+    pub token_path: PathBuf,
+    /// The endpoint tokens are requested from, `TOKEN_URL` except in tests
+    pub token_url: String,
+}
+
+/// Masks the client secret
+impl std::fmt::Debug for OAuthConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OAuthConfig")
+            .field("client_id", &self.client_id)
+            .field("client_secret", &"<redacted>")
+            .field("port", &self.port)
+            .field("token_path", &self.token_path)
+            .field("token_url", &self.token_url)
+            .finish()
+    }
+}
+
+impl OAuthConfig {
+    /// The callback URL the authorization code is sent to
+    pub fn redirect_uri(&self) -> String {
+        format!("http://localhost:{}/callback", self.port)
+    }
+
+    /// The URL the user opens to grant us access
+    pub fn authorize_url(&self, state: &str) -> String {
+        Url::parse_with_params(
+            AUTHORIZE_URL,
+            &[
+                ("audience", "api.atlassian.com"),
+                ("client_id", &self.client_id),
+                ("scope", SCOPES),
+                ("redirect_uri", &self.redirect_uri()),
+                ("state", state),
+                ("response_type", "code"),
+                ("prompt", "consent"),
+            ],
+        )
+        .unwrap()
+        .to_string()
+    }
+}
+
+/// The tokens of an authorized session, persisted between runs
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct OAuthTokens {
+    pub access_token: String,
+    pub refresh_token: String,
+    /// When the access token expires, as a UNIX timestamp
+    pub expires_at: i64,
+    /// The ID of the Jira Cloud site, which OAuth requests are addressed to
+    pub cloud_id: String,
+}
+
+impl OAuthTokens {
+    /// Whether the access token has expired, or is about to
+    pub fn is_expired(&self, now: OffsetDateTime) -> bool {
+        now.unix_timestamp() + EXPIRY_MARGIN >= self.expires_at
+    }
+
+    /// The base URL of REST API requests to the site
+    pub fn api_base_url(&self) -> String {
+        format!("https://api.atlassian.com/ex/jira/{}", self.cloud_id)
+    }
+
+    /// Loads stored tokens
+    pub fn load(path: &Path) -> Result<OAuthTokens, String> {
+        let body = std::fs::read_to_string(path).map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => {
+                "Not logged in to Jira, run `timew jirapush login` first".to_string()
+            }
+            _ => format!("Error reading OAuth tokens {}: {}", path.display(), e),
+        })?;
+        serde_json::from_str(&body)
+            .map_err(|e| format!("Error parsing OAuth tokens {}: {}", path.display(), e))
+    }
+
+    /// Stores the tokens, readable only by the current user.
+    /// They're written to a temporary file first, so an interrupted write can't lose the rotated refresh token.
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let body = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Error serializing OAuth tokens: {}", e))?;
+        let tmp_path = path.with_extension("json.tmp");
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options
+            .open(&tmp_path)
+            .and_then(|mut f| {
+                // The mode only applies to new files, not to one left over from an earlier run
+                #[cfg(unix)]
+                f.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+                std::io::Write::write_all(&mut f, body.as_bytes())
+            })
+            .and_then(|_| std::fs::rename(&tmp_path, path))
+            .map_err(|e| format!("Error writing OAuth tokens {}: {}", path.display(), e))
+    }
+}
+
+/// Jira's response to a token request
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: i64,
+    refresh_token: Option<String>,
+}
+
+/// A Jira Cloud site the user granted us access to
+#[derive(Deserialize)]
+struct AccessibleResource {
+    id: String,
+    url: String,
+}
+
+/// Requests tokens from the token endpoint
+async fn request_tokens(
+    rc: &Client,
+    config: &OAuthConfig,
+    body: serde_json::Value,
+) -> Result<TokenResponse, JiraError> {
+    let r = rc
+        .post(&config.token_url)
+        .header("Content-Type", "application/json")
+        .body(body.to_string())
+        .send()
+        .await?;
+    match r.status() {
+        s if s.is_success() => {
+            let body = r.text().await?;
+            serde_json::from_str(&body).map_err(|e| JiraError::Parse(e.to_string()))
+        }
+        s => {
+            debug!("Token response: {}", r.text().await.unwrap_or_default());
+            Err(JiraError::Authentication(match s {
+                StatusCode::BAD_REQUEST => StatusCode::UNAUTHORIZED,
+                s => s,
+            }))
+        }
+    }
+}
+
+/// An authorized OAuth session, shared by all requests of a run
+pub struct OAuthSession {
+    config: OAuthConfig,
+    tokens: Mutex<OAuthTokens>,
+}
+
+impl std::fmt::Debug for OAuthSession {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OAuthSession")
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+impl OAuthSession {
+    pub fn new(config: OAuthConfig, tokens: OAuthTokens) -> OAuthSession {
+        OAuthSession {
+            config,
+            tokens: Mutex::new(tokens),
+        }
+    }
+
+    /// A valid access token, refreshed first if it has expired.
+    /// Refresh tokens rotate, so the new tokens are stored immediately.
+    pub async fn access_token(&self, rc: &Client) -> Result<String, JiraError> {
+        let mut tokens = self.tokens.lock().await;
+        if !tokens.is_expired(OffsetDateTime::now_utc()) {
+            return Ok(tokens.access_token.clone());
+        }
+        info!("Refreshing the OAuth access token");
+        let response = request_tokens(
+            rc,
+            &self.config,
+            serde_json::json!({
+                "grant_type": "refresh_token",
+                "client_id": self.config.client_id,
+                "client_secret": self.config.client_secret,
+                "refresh_token": tokens.refresh_token,
+            }),
+        )
+        .await
+        .inspect_err(|_| {
+            error!("Error refreshing the OAuth access token, run `timew jirapush login` again");
+        })?;
+        tokens.access_token = response.access_token;
+        tokens.expires_at = OffsetDateTime::now_utc().unix_timestamp() + response.expires_in;
+        if let Some(refresh_token) = response.refresh_token {
+            tokens.refresh_token = refresh_token;
+        }
+        if let Err(e) = tokens.save(&self.config.token_path) {
+            error!("{}", e);
+        }
+        Ok(tokens.access_token.clone())
+    }
+}
+
+/// Extracts the authorization code from the request line of a redirect to the loopback listener.
+/// Returns `None` for unrelated requests, such as for a favicon.
+pub fn parse_callback(request_line: &str, state: &str) -> Option<Result<String, String>> {
+    let path = request_line.split_whitespace().nth(1)?;
+    let url = Url::parse(&format!("http://localhost{}", path)).ok()?;
+    if url.path() != "/callback" {
+        return None;
+    }
+    let param = |name: &str| {
+        url.query_pairs()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.to_string())
+    };
+    if let Some(e) = param("error") {
+        return Some(Err(format!("Authorization was denied: {}", e)));
+    }
+    if param("state").as_deref() != Some(state) {
+        return Some(Err("Authorization state mismatch, please try again".to_string()));
+    }
+    Some(param("code").ok_or_else(|| "No authorization code received".to_string()))
+}
+
+/// Waits for the browser to be redirected to the loopback listener, returning the authorization code
+async fn receive_code(port: u16, state: &str) -> Result<String, String> {
+    let listener = TcpListener::bind(("127.0.0.1", port))
+        .await
+        .map_err(|e| format!("Error listening on port {}: {}", port, e))?;
+    loop {
+        let (mut stream, _) = listener
+            .accept()
+            .await
+            .map_err(|e| format!("Error accepting the redirect: {}", e))?;
+        let mut buf = vec![0; 8192];
+        let n = stream.read(&mut buf).await.unwrap_or(0);
+        let request = String::from_utf8_lossy(&buf[..n]);
+        let result = match parse_callback(request.lines().next().unwrap_or_default(), state) {
+            Some(result) => result,
+            None => {
+                let _ = stream
+                    .write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n")
+                    .await;
+                continue;
+            }
+        };
+        let message = match &result {
+            Ok(_) => "Logged in to Jira, you can close this window.".to_string(),
+            Err(e) => e.clone(),
+        };
+        let _ = stream
+            .write_all(
+                format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    message.len(),
+                    message
+                )
+                .as_bytes(),
+            )
+            .await;
+        return result;
+    }
+}
+
+/// Runs the authorization code flow for the Jira Cloud site at `instance_url`, and stores the tokens
+pub async fn login(rc: &Client, config: &OAuthConfig, instance_url: &str) -> Result<OAuthTokens, String> {
+    let state: String = std::iter::repeat_with(fastrand::alphanumeric).take(32).collect();
+    println!("Open this URL in your browser to grant access to Jira:\n\n{}\n", config.authorize_url(&state));
+    let code = tokio::time::timeout(
+        std::time::Duration::from_secs(300),
+        receive_code(config.port, &state),
+    )
+    .await
+    .map_err(|_| "Timed out waiting for authorization".to_string())??;
+
+    let response = request_tokens(
+        rc,
+        config,
+        serde_json::json!({
+            "grant_type": "authorization_code",
+            "client_id": config.client_id,
+            "client_secret": config.client_secret,
+            "code": code,
+            "redirect_uri": config.redirect_uri(),
+        }),
+    )
+    .await
+    .map_err(|e| format!("Error requesting tokens: {}", e))?;
+    let refresh_token = response
+        .refresh_token
+        .ok_or_else(|| "No refresh token received, is offline_access allowed?".to_string())?;
+
+    // Find the site matching the configured URL among those we were granted access to
+    let r = rc
+        .get(RESOURCES_URL)
+        .bearer_auth(&response.access_token)
+        .send()
+        .await
+        .map_err(|e| format!("Error listing accessible sites: {}", e))?;
+    let body = r
+        .text()
+        .await
+        .map_err(|e| format!("Error listing accessible sites: {}", e))?;
+    let resources: Vec<AccessibleResource> = serde_json::from_str(&body)
+        .map_err(|e| format!("Error parsing accessible sites: {}", e))?;
+    let site = resources
+        .into_iter()
+        .find(|r| r.url.trim_end_matches('/') == instance_url.trim_end_matches('/'))
+        .ok_or_else(|| format!("Access to {} was not granted", instance_url))?;
+
+    let tokens = OAuthTokens {
+        access_token: response.access_token,
+        refresh_token,
+        expires_at: OffsetDateTime::now_utc().unix_timestamp() + response.expires_in,
+        cloud_id: site.id,
+    };
+    tokens.save(&config.token_path)?;
+    Ok(tokens)
+}
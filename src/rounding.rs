@@ -0,0 +1,59 @@
+use std::str::FromStr;
+
+/// How worklog durations are rounded to the configured increment
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RoundingMode {
+    None,
+    Nearest,
+    Up,
+    Down,
+}
+
+impl FromStr for RoundingMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(RoundingMode::None),
+            "nearest" => Ok(RoundingMode::Nearest),
+            "up" => Ok(RoundingMode::Up),
+            "down" => Ok(RoundingMode::Down),
+            _ => Err("expected one of none, nearest, up, or down".to_string()),
+        }
+    }
+}
+
+/// A policy for rounding worklog durations before upload
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoundingPolicy {
+    pub mode: RoundingMode,
+    /// The increment to round to, in seconds
+    pub increment: i64,
+    /// The minimum billable duration, in seconds
+    pub minimum: i64,
+}
+
+impl Default for RoundingPolicy {
+    fn default() -> Self {
+        RoundingPolicy {
+            mode: RoundingMode::None,
+            increment: 60,
+            minimum: 0,
+        }
+    }
+}
+
+impl RoundingPolicy {
+    /// Applies the policy to a duration in seconds
+    pub fn apply(&self, seconds: i64) -> i64 {
+        let rounded = match self.mode {
+            RoundingMode::None => seconds,
+            RoundingMode::Nearest => {
+                (seconds + self.increment / 2) / self.increment * self.increment
+            }
+            RoundingMode::Up => (seconds + self.increment - 1) / self.increment * self.increment,
+            RoundingMode::Down => seconds / self.increment * self.increment,
+        };
+        rounded.max(self.minimum)
+    }
+}
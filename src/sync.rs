@@ -0,0 +1,818 @@
+//! The sync pipeline: selecting the intervals to upload, building worklogs from them,
+//! and syncing those with Jira.
+use crate::config::{Aggregation, Config};
+use crate::jira::{self, JiraClient, JiraError, JiraWorklog, WorklogComment};
+use crate::ledger::{Ledger, LedgerEntry};
+use crate::timewarrior::{parse_tw_time, Tagger, TimeWarriorLog};
+use crate::timezone::Timezone;
+use log::{debug, error, info, warn};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use time::OffsetDateTime;
+use tokio::sync::{OnceCell, Semaphore};
+
+/// The format of start times in reports
+pub const REPORT_TIME_FORMAT: &str = "%F %H:%M:%S %z";
+
+/// A single row of the report printed for dry runs and reconciliation
+#[derive(Debug)]
+pub struct ReportEntry {
+    pub issue: String,
+    pub start: String,
+    pub duration: Option<i64>,
+    pub decision: String,
+}
+
+/// A worklog to be uploaded or updated, built from one or more intervals
+#[derive(Debug)]
+pub struct PendingWorklog {
+    pub issue: String,
+    pub logs: Vec<TimeWarriorLog>,
+    /// The ledger records replaced by this worklog, if it updates an existing worklog
+    pub previous: Vec<LedgerEntry>,
+    /// The ledger records of unchanged intervals contributing to the updated worklog
+    pub unchanged: Vec<LedgerEntry>,
+}
+
+impl PendingWorklog {
+    /// The ID of the existing worklog being updated, if any
+    pub fn worklog_id(&self) -> Option<&str> {
+        self.previous.first().map(|e| e.worklog_id.as_str())
+    }
+
+    /// The start and end times of all intervals contributing to the worklog, in order.
+    /// Intervals still running are left out, as they have no end yet.
+    pub fn intervals(&self) -> Vec<(&String, &String)> {
+        let mut intervals: Vec<(&String, &String)> = self
+            .logs
+            .iter()
+            .filter_map(|l| Some((&l.start, l.end.as_ref()?)))
+            .chain(self.unchanged.iter().map(|e| (&e.start, &e.end)))
+            .collect();
+        intervals.sort();
+        intervals
+    }
+}
+
+/// Groups pending logs into worklogs.
+/// Edited intervals are grouped by the worklog they were uploaded to, so the worklog can be updated as a whole.
+/// New intervals each become their own worklog, unless aggregated by issue and calendar day.
+/// Intervals still running are left out, as their duration isn't known yet.
+pub fn group_pending_logs(
+    pending_logs: Vec<(String, TimeWarriorLog, Option<LedgerEntry>)>,
+    aggregation: Aggregation,
+    timezone: &Timezone,
+    ledger: &Ledger,
+) -> Vec<PendingWorklog> {
+    let mut worklogs = Vec::<PendingWorklog>::new();
+    let mut new_keys = Vec::<(String, Option<time::Date>)>::new();
+    let mut new_worklogs = Vec::<PendingWorklog>::new();
+    for (issue, log, previous) in pending_logs {
+        if log.end.is_none() {
+            debug!("Leaving out running interval @{} for {}", log.id, issue);
+            continue;
+        }
+        match previous {
+            Some(previous) => {
+                match worklogs
+                    .iter_mut()
+                    .find(|w| w.worklog_id() == Some(previous.worklog_id.as_str()))
+                {
+                    Some(w) => {
+                        w.logs.push(log);
+                        w.previous.push(previous);
+                    }
+                    None => worklogs.push(PendingWorklog {
+                        issue,
+                        logs: vec![log],
+                        previous: vec![previous],
+                        unchanged: vec![],
+                    }),
+                }
+            }
+            None => {
+                let day = parse_tw_time(&log.start, timezone).ok().map(|s| s.date());
+                let key = (issue.clone(), day);
+                match new_keys.iter().position(|k| *k == key) {
+                    Some(i) if aggregation == Aggregation::Daily => new_worklogs[i].logs.push(log),
+                    _ => {
+                        new_keys.push(key);
+                        new_worklogs.push(PendingWorklog {
+                            issue,
+                            logs: vec![log],
+                            previous: vec![],
+                            unchanged: vec![],
+                        });
+                    }
+                }
+            }
+        }
+    }
+    // Updated worklogs keep any intervals which weren't edited
+    for w in worklogs.iter_mut() {
+        let id = w.worklog_id().unwrap().to_string();
+        w.unchanged = ledger
+            .find_worklog(&id)
+            .into_iter()
+            .filter(|e| !w.previous.contains(e))
+            .cloned()
+            .collect();
+    }
+    worklogs.extend(new_worklogs);
+    worklogs
+}
+
+/// Parses a worklog start time returned by Jira
+pub fn parse_jira_time(started: &str) -> Result<OffsetDateTime, time::ParseError> {
+    // Jira stores milliseconds which cannot be easily parsed here as there's no formatting directive
+    // We will superimpose 0's there so we can still parse.
+    match (started.get(..20), started.get(23..)) {
+        (Some(head), Some(tail)) => {
+            OffsetDateTime::parse(format!("{}000{}", head, tail), "%Y-%m-%dT%H:%M:%S.000%z")
+        }
+        _ => Err(time::ParseError::UnexpectedEndOfString),
+    }
+}
+
+/// Formats a number of seconds as hours, minutes and seconds
+pub fn format_duration(seconds: i64) -> String {
+    format!(
+        "{}:{:02}:{:02}",
+        seconds / 3600,
+        (seconds % 3600) / 60,
+        seconds % 60
+    )
+}
+
+/// Records an upload in the ledger, replacing the records of its previous upload, and persists it immediately
+fn record_upload(ledger: &Mutex<Ledger>, previous: &[LedgerEntry], entries: Vec<LedgerEntry>) {
+    let mut ledger = ledger.lock().unwrap();
+    for entry in previous {
+        ledger.remove(entry);
+    }
+    for entry in entries {
+        ledger.record(entry);
+    }
+    if let Err(e) = ledger.save() {
+        error!("{}", e);
+    }
+}
+
+/// Prints a report as a table to stdout
+pub fn print_report(entries: &mut [ReportEntry]) {
+    entries.sort_by(|a, b| a.start.cmp(&b.start));
+    let durations: Vec<String> = entries
+        .iter()
+        .map(|e| e.duration.map(format_duration).unwrap_or_else(|| "-".to_string()))
+        .collect();
+    let issue_w = entries.iter().map(|e| e.issue.len()).fold(5, usize::max);
+    let start_w = entries.iter().map(|e| e.start.len()).fold(5, usize::max);
+    let duration_w = durations.iter().map(|d| d.len()).fold(8, usize::max);
+    println!(
+        "{:issue_w$}  {:start_w$}  {:>duration_w$}  Decision",
+        "Issue",
+        "Start",
+        "Duration",
+        issue_w = issue_w,
+        start_w = start_w,
+        duration_w = duration_w
+    );
+    for (entry, duration) in entries.iter().zip(durations) {
+        println!(
+            "{:issue_w$}  {:start_w$}  {:>duration_w$}  {}",
+            entry.issue,
+            entry.start,
+            duration,
+            entry.decision,
+            issue_w = issue_w,
+            start_w = start_w,
+            duration_w = duration_w
+        );
+    }
+}
+
+/// Reports a failed Jira call for an issue.
+/// If our credentials were rejected, flags that all further work should be aborted, reporting it only once.
+fn report_jira_error(action: &str, issue: &str, e: &JiraError, abort: &AtomicBool) {
+    match e {
+        JiraError::Authentication(_) => {
+            if !abort.swap(true, Ordering::SeqCst) {
+                error!("{}. Aborting, nothing further will be sent to Jira.", e);
+            }
+        }
+        // A single project may not let us log work, which shouldn't stop the others
+        JiraError::Forbidden => {
+            warn!("Permission denied for {} while {}, skipping.", issue, action);
+        }
+        JiraError::NotFound => {
+            warn!("Issue {} not found while {}, skipping.", issue, action);
+        }
+        JiraError::RateLimited => {
+            warn!(
+                "Rate limited by Jira while {} for {}, skipping until the next run.",
+                action, issue
+            );
+        }
+        _ => {
+            warn!("Error {} for {}: {}", action, issue, e);
+        }
+    }
+}
+
+/// Finds worklogs by the configured user which have no matching local interval, e.g. because
+/// the interval was deleted or untagged. The issues of local intervals are checked, as well as
+/// those of uploads recorded in the ledger, whose intervals may be gone entirely.
+/// A worklog recorded in the ledger is orphaned only if none of its intervals remain locally,
+/// as recorded or since edited; any other worklog if no local interval starts at the same time.
+/// Only worklogs started within the given range are considered.
+/// If `delete` is set (and this isn't a dry run), orphaned worklogs are deleted from Jira,
+/// but only those recorded in the ledger, as others were not created by us.
+pub async fn reconcile_worklogs(
+    client: &impl JiraClient,
+    local_logs: &[(String, &TimeWarriorLog)],
+    range: (Option<OffsetDateTime>, Option<OffsetDateTime>),
+    delete: bool,
+    dry_run: bool,
+    ledger: &Mutex<Ledger>,
+) -> Vec<ReportEntry> {
+    let jc = client.connection();
+    let abort = AtomicBool::new(false);
+    let in_range = |t: OffsetDateTime| range.0.is_none_or(|s| t >= s) && range.1.is_none_or(|e| t < e);
+    let mut issues: Vec<String> = local_logs.iter().map(|(issue, _)| issue.clone()).collect();
+    // Ledger times are TimeWarrior's UTC timestamps
+    issues.extend(
+        ledger
+            .lock()
+            .unwrap()
+            .entries()
+            .iter()
+            .filter(|e| parse_tw_time(&e.start, &Timezone::default()).is_ok_and(in_range))
+            .map(|e| e.issue.clone()),
+    );
+    issues.sort();
+    issues.dedup();
+
+    let mut entries = Vec::new();
+    for issue in &issues {
+        // Worklogs started before the range can't be orphans, so don't fetch them
+        let started_after = range.0.map(|s| s - time::Duration::second());
+        let worklogs = match client.get_worklogs(issue, started_after).await {
+            Ok(worklogs) => worklogs,
+            Err(e) => {
+                report_jira_error("fetching worklogs", issue, &e, &abort);
+                match abort.load(Ordering::SeqCst) {
+                    true => break,
+                    false => continue,
+                }
+            }
+        };
+        for wl in worklogs {
+            if !jc.is_author(&wl.author) {
+                continue;
+            }
+            let started = match parse_jira_time(&wl.started) {
+                Ok(s) => s,
+                Err(e) => {
+                    warn!("Error parsing worklog start {} for {}: {}", wl.started, issue, e);
+                    continue;
+                }
+            };
+            if !in_range(started) {
+                continue;
+            }
+            let id = wl.id.clone().unwrap_or_default();
+            let recorded: Vec<LedgerEntry> =
+                ledger.lock().unwrap().find_worklog(&id).into_iter().cloned().collect();
+            let uploaded = !recorded.is_empty();
+            let has_local = local_logs
+                .iter()
+                .filter(|(local_issue, _)| local_issue == issue)
+                .any(|(_, l)| match uploaded {
+                    // E.g. an aggregated worklog keeps its other intervals, or an interval was edited
+                    true => {
+                        let end = l.end.as_deref().unwrap_or_default();
+                        recorded.iter().any(|e| e.matches(&l.start, end, issue))
+                    }
+                    false => parse_tw_time(&l.start, &Timezone::default()).is_ok_and(|s| s == started),
+                });
+            if has_local {
+                continue;
+            }
+            let decision = if !delete {
+                format!("orphaned worklog {}", id)
+            } else if !uploaded {
+                // E.g. logged in Jira by hand, or by another tool
+                format!("orphaned worklog {} (not in upload ledger, not deleted)", id)
+            } else if dry_run {
+                format!("would delete orphaned worklog {}", id)
+            } else {
+                match client.delete_worklog(issue, &id).await {
+                    Ok(_) => {
+                        let mut ledger = ledger.lock().unwrap();
+                        ledger.forget(&id);
+                        if let Err(e) = ledger.save() {
+                            error!("{}", e);
+                        }
+                        info!("Deleted orphaned worklog {} for {}", id, issue);
+                        format!("deleted orphaned worklog {}", id)
+                    }
+                    Err(e) => {
+                        report_jira_error("deleting orphaned worklog", issue, &e, &abort);
+                        format!("error deleting orphaned worklog {}: {}", id, e)
+                    }
+                }
+            };
+            entries.push(ReportEntry {
+                issue: issue.clone(),
+                start: started.format(REPORT_TIME_FORMAT),
+                duration: Some(wl.time_spent_seconds),
+                decision,
+            });
+        }
+    }
+    entries
+}
+
+/// The outcome of checking which intervals need to be uploaded
+#[derive(Debug, Default)]
+pub struct Selection {
+    /// Intervals to upload, with their issue and, if edited since their upload, the record of it
+    pub pending: Vec<(String, TimeWarriorLog, Option<LedgerEntry>)>,
+    /// Intervals recorded in the ledger but not tagged, e.g. because tagging failed after the upload
+    pub untagged: Vec<TimeWarriorLog>,
+    /// Report rows for the intervals which are skipped, and why
+    pub skipped: Vec<ReportEntry>,
+    /// Interval annotations by interval start, for the comments of worklogs
+    pub annotations: HashMap<String, String>,
+}
+
+/// Determines which intervals need to be uploaded, consulting the uploaded tag and the ledger
+pub fn select_pending_logs(tw_logs: Vec<TimeWarriorLog>, config: &Config, ledger: &Ledger) -> Selection {
+    let upload_tag = &config.uploaded_tag;
+    let mut selection = Selection {
+        // Keep track of annotations by interval start, for the comments of aggregated worklogs
+        annotations: tw_logs
+            .iter()
+            .filter_map(|l| Some((l.start.clone(), l.annotation.clone()?)))
+            .collect(),
+        ..Selection::default()
+    };
+    for tw_log in tw_logs {
+        // Check if log is uploaded, and if not, if it's complete and so needs to be
+        let is_uploaded = tw_log.tags.contains(upload_tag);
+        let is_complete = tw_log.end.is_some();
+        // Find the first tag referencing a Jira issue
+        let issue = config.issue_matcher.find_issue(&tw_log.tags);
+        // Check whether the ledger has a record of uploading this interval,
+        // and if it was uploaded but since edited, find the record of its previous times
+        let (in_ledger, edited_entry) = match (&issue, &tw_log.end) {
+            (Some(issue), Some(end)) => match ledger.find(&tw_log.start, end, issue) {
+                Some(_) => (true, None),
+                None if is_uploaded => {
+                    (false, ledger.find_edited(&tw_log.start, end, issue).cloned())
+                }
+                None => (false, None),
+            },
+            _ => (false, None),
+        };
+        if is_complete && !in_ledger && (!is_uploaded || edited_entry.is_some()) {
+            if let Some(issue) = issue {
+                selection.pending.push((issue, tw_log, edited_entry));
+                continue;
+            }
+        }
+        let reason = if is_uploaded && is_complete && issue.is_some() && !in_ledger {
+            // E.g. moved with `timew move` since the upload, so its worklog may be out of date
+            info!(
+                "Interval @{} is tagged {} but not in the upload ledger, its worklog may need updating",
+                tw_log.id, upload_tag
+            );
+            format!("skipped (tagged {}, but not in upload ledger)", upload_tag)
+        } else if is_uploaded {
+            format!("skipped (tagged {})", upload_tag)
+        } else if in_ledger {
+            "skipped (recorded in upload ledger)".to_string()
+        } else if !is_complete {
+            "skipped (interval still running)".to_string()
+        } else {
+            "skipped (no Jira issue tag)".to_string()
+        };
+        let start = parse_tw_time(&tw_log.start, &config.timezone);
+        let end = tw_log.end.as_ref().map(|e| parse_tw_time(e, &config.timezone));
+        selection.skipped.push(ReportEntry {
+            issue: issue.unwrap_or_else(|| "-".to_string()),
+            start: match &start {
+                Ok(s) => s.format(REPORT_TIME_FORMAT),
+                Err(_) => tw_log.start.clone(),
+            },
+            duration: match (start, end) {
+                (Ok(s), Some(Ok(e))) => Some((e - s).whole_seconds()),
+                _ => None,
+            },
+            decision: reason,
+        });
+        if in_ledger && !is_uploaded {
+            selection.untagged.push(tw_log);
+        }
+    }
+    selection
+}
+
+/// A worklog ready to be synced, along with the intervals it was built from
+#[derive(Debug)]
+pub struct PlannedWorklog {
+    pub pending: PendingWorklog,
+    pub worklog: JiraWorklog,
+    pub start: OffsetDateTime,
+    /// Details about how the worklog was built, for the report
+    pub note: String,
+    /// The duration before rounding, if rounding changed it
+    pub rounded_from: Option<i64>,
+}
+
+/// Builds the Jira worklogs for grouped intervals, applying rounding and collecting annotations as comments.
+/// Worklogs without any complete interval are left out.
+pub fn build_worklogs(
+    pending_worklogs: Vec<PendingWorklog>,
+    annotations: &HashMap<String, String>,
+    config: &Config,
+    jc: &jira::JiraConnection,
+) -> Vec<PlannedWorklog> {
+    let mut planned = Vec::new();
+    for pending in pending_worklogs {
+        // Parse sparse ISO8601 dates handed off by TimeWarrior
+        let intervals: Vec<(OffsetDateTime, OffsetDateTime)> = match pending
+            .intervals()
+            .iter()
+            .map(|(s, e)| Ok((parse_tw_time(s, &config.timezone)?, parse_tw_time(e, &config.timezone)?)))
+            .collect::<Result<_, time::ParseError>>()
+        {
+            Ok(intervals) => intervals,
+            Err(e) => {
+                warn!("Error parsing interval times for {}: {}", pending.issue, e);
+                continue;
+            }
+        };
+        // The worklog starts with its earliest interval, and lasts for all of them
+        let start = match intervals.iter().map(|(s, _)| *s).min() {
+            Some(start) => start,
+            None => {
+                warn!("No complete intervals to log for {}", pending.issue);
+                continue;
+            }
+        };
+        let duration: i64 = intervals.iter().map(|(s, e)| (*e - *s).whole_seconds()).sum();
+
+        // Round the duration according to policy
+        let rounded_duration = config.rounding.apply(duration);
+        let mut note = String::new();
+        if intervals.len() > 1 {
+            note += &format!(" ({} intervals)", intervals.len());
+        }
+        if rounded_duration != duration {
+            note += &format!(" (rounded from {})", format_duration(duration));
+        }
+
+        // Collect the annotations of all contributing intervals for the comment
+        let mut interval_annotations: Vec<&String> = pending
+            .intervals()
+            .iter()
+            .filter_map(|(s, _)| annotations.get(*s))
+            .filter(|a| !a.trim().is_empty())
+            .collect();
+        interval_annotations.dedup();
+        let comment = interval_annotations
+            .iter()
+            .map(|a| a.as_str())
+            .collect::<Vec<&str>>()
+            .join("\n");
+
+        // Construct a compatible Jira worklog
+        let worklog = JiraWorklog {
+            id: None,
+            started: start.format(jira::WORKLOG_TIME_FORMAT),
+            time_spent_seconds: rounded_duration,
+            author: jc.author(),
+            comment: match comment.is_empty() {
+                true => None,
+                false => Some(WorklogComment::new(jc, &comment)),
+            },
+        };
+        planned.push(PlannedWorklog {
+            pending,
+            worklog,
+            start,
+            note,
+            rounded_from: match rounded_duration != duration {
+                true => Some(duration),
+                false => None,
+            },
+        });
+    }
+    planned
+}
+
+/// Tags intervals as uploaded in a single batch, logging the outcome.
+/// The intervals are already recorded in the ledger, so if tagging fails, it's retried on the next run.
+fn tag_uploaded(tagger: &impl Tagger, logs: &[TimeWarriorLog], tag: &str) {
+    if logs.is_empty() {
+        return;
+    }
+    let logs: Vec<&TimeWarriorLog> = logs.iter().collect();
+    match tagger.tag(&logs, tag) {
+        Ok(_) => {
+            info!("Marked {} intervals as uploaded", logs.len());
+        }
+        Err(e) => {
+            warn!("Error marking intervals as uploaded: {}", e);
+        }
+    }
+}
+
+/// What became of a single worklog
+enum Synced {
+    /// The report row of a dry run
+    Report(ReportEntry),
+    /// Uploaded or updated, with the rounding applied to it, if any
+    Sent(Option<String>),
+    /// Not sent, e.g. because it already exists or an error occurred
+    Skipped,
+}
+
+/// The state shared by all worklogs synced in a run
+struct SyncRun<'a, C> {
+    client: &'a C,
+    config: &'a Config,
+    ledger: &'a Mutex<Ledger>,
+    /// Set when Jira rejects our credentials, so we stop sending requests
+    abort: AtomicBool,
+    /// The intervals to tag as uploaded once all worklogs are synced
+    to_tag: Mutex<Vec<TimeWarriorLog>>,
+}
+
+impl<C: JiraClient> SyncRun<'_, C> {
+    /// Syncs a single worklog: updates it if its intervals were edited,
+    /// or uploads it unless it already exists. Intervals to be tagged as uploaded are added to `to_tag`.
+    async fn sync_worklog(
+        &self,
+        planned: PlannedWorklog,
+        existing_worklogs: &OnceCell<Result<Vec<JiraWorklog>, JiraError>>,
+        started_after: Option<OffsetDateTime>,
+    ) -> Synced {
+        let SyncRun {
+            client,
+            config,
+            ledger,
+            abort,
+            to_tag,
+        } = self;
+        let jc = client.connection();
+        let dry_run = config.dry_run;
+        let PlannedWorklog {
+            pending,
+            worklog,
+            start,
+            note,
+            rounded_from,
+        } = planned;
+        let issue = &pending.issue;
+        let ledger_entries = |worklog_id: &str| -> Vec<LedgerEntry> {
+            pending
+                .logs
+                .iter()
+                .filter_map(|log| {
+                    Some(LedgerEntry {
+                        start: log.start.clone(),
+                        end: log.end.clone()?,
+                        issue: issue.clone(),
+                        worklog_id: worklog_id.to_string(),
+                    })
+                })
+                .collect()
+        };
+        let dry_run_entry = |decision: &str| ReportEntry {
+            issue: issue.clone(),
+            start: start.format(REPORT_TIME_FORMAT),
+            duration: Some(worklog.time_spent_seconds),
+            decision: decision.to_string() + &note,
+        };
+        let adjustment = rounded_from.map(|duration| {
+            format!(
+                "Rounded {} worklog at {} from {} to {}",
+                issue,
+                start.format(REPORT_TIME_FORMAT),
+                format_duration(duration),
+                format_duration(worklog.time_spent_seconds)
+            )
+        });
+        // Intervals edited since their upload update the existing worklog instead
+        if let Some(id) = pending.worklog_id() {
+            if dry_run {
+                return Synced::Report(dry_run_entry(&format!("would update worklog {}", id)));
+            }
+            return match client.update_worklog(issue, id, &worklog).await {
+                Ok(_) => {
+                    record_upload(ledger, &pending.previous, ledger_entries(id));
+                    info!("Updated worklog {} for {}", id, issue);
+                    Synced::Sent(adjustment)
+                }
+                Err(e) => {
+                    debug!("Failed to update {:?}", worklog);
+                    report_jira_error("updating worklog", issue, &e, abort);
+                    Synced::Skipped
+                }
+            };
+        }
+        // Check to see if an existing worklog at that time exists (unless configured otherwise)
+        if config.skip_existing {
+            // Fetch existing logs
+            let existing_logs = match existing_worklogs
+                .get_or_init(|| client.get_worklogs(issue, started_after))
+                .await
+            {
+                Ok(logs) => logs,
+                Err(e) => {
+                    report_jira_error("fetching worklogs", issue, e, abort);
+                    if dry_run {
+                        return Synced::Report(dry_run_entry(&format!("skipped (error: {})", e)));
+                    }
+                    return Synced::Skipped;
+                }
+            };
+            debug!("Existing logs: {:?}", existing_logs);
+            // Compare logs
+            let existing = existing_logs
+                .iter()
+                .find(|wl| parse_jira_time(&wl.started).ok() == Some(start) && jc.is_author(&wl.author));
+            // We have a log here already, skip this one.
+            if let Some(existing) = existing {
+                if dry_run {
+                    return Synced::Report(dry_run_entry("already exists"));
+                }
+                if let Some(id) = &existing.id {
+                    record_upload(ledger, &[], ledger_entries(id));
+                }
+                // Tag the intervals as uploaded
+                info!("Log already exists for {}, marking as uploaded.", issue);
+                to_tag.lock().unwrap().extend(pending.logs);
+                return Synced::Skipped;
+            }
+        }
+        if dry_run {
+            return Synced::Report(dry_run_entry("would upload"));
+        }
+        // Upload
+        match client.upload_worklog(issue, &worklog).await {
+            Ok(id) => {
+                // Record the upload before tagging, so a tagging failure can't cause a duplicate
+                record_upload(ledger, &[], ledger_entries(&id));
+                // Tag the intervals as uploaded
+                info!("Logged for {}", issue);
+                to_tag.lock().unwrap().extend(pending.logs);
+                Synced::Sent(adjustment)
+            }
+            Err(e) => {
+                debug!("Failed to upload {:?}", worklog);
+                report_jira_error("uploading worklog", issue, &e, abort);
+                Synced::Skipped
+            }
+        }
+    }
+}
+
+/// Syncs worklogs with Jira, with a limited number in flight at once,
+/// then tags all synced intervals as uploaded at once.
+/// Returns the report rows of a dry run, and the rounding of the worklogs sent.
+pub async fn sync_worklogs(
+    client: &impl JiraClient,
+    tagger: &impl Tagger,
+    config: &Config,
+    planned: Vec<PlannedWorklog>,
+    ledger: &Mutex<Ledger>,
+) -> Outcome {
+    let semaphore = Semaphore::new(config.max_concurrency);
+    let sync_run = SyncRun {
+        client,
+        config,
+        ledger,
+        abort: AtomicBool::new(false),
+        to_tag: Mutex::new(Vec::new()),
+    };
+    // Existing worklogs are fetched at most once per issue, and shared by all of its worklogs
+    let mut existing_worklogs = HashMap::<String, OnceCell<Result<Vec<JiraWorklog>, JiraError>>>::new();
+    // Duplicates can't start before an issue's earliest pending worklog, so don't fetch older worklogs
+    let mut earliest_starts = HashMap::<String, OffsetDateTime>::new();
+    for p in &planned {
+        existing_worklogs.entry(p.pending.issue.clone()).or_default();
+        let earliest = earliest_starts.entry(p.pending.issue.clone()).or_insert(p.start);
+        *earliest = p.start.min(*earliest);
+    }
+    let tasks = planned.into_iter().map(|p| {
+        let existing_worklogs = &existing_worklogs[&p.pending.issue];
+        let started_after = earliest_starts
+            .get(&p.pending.issue)
+            .map(|s| *s - time::Duration::second());
+        let (semaphore, sync_run) = (&semaphore, &sync_run);
+        async move {
+            let _permit = semaphore.acquire().await.unwrap();
+            if sync_run.abort.load(Ordering::SeqCst) {
+                // Don't let a dry run's report silently leave out what we didn't get to
+                return match config.dry_run {
+                    true => Synced::Report(ReportEntry {
+                        issue: p.pending.issue.clone(),
+                        start: p.start.format(REPORT_TIME_FORMAT),
+                        duration: Some(p.worklog.time_spent_seconds),
+                        decision: "skipped (aborted: Jira rejected the credentials)".to_string(),
+                    }),
+                    false => Synced::Skipped,
+                };
+            }
+            sync_run.sync_worklog(p, existing_worklogs, started_after).await
+        }
+    });
+    let mut outcome = Outcome::default();
+    for synced in futures::future::join_all(tasks).await {
+        match synced {
+            Synced::Report(entry) => outcome.report.push(entry),
+            Synced::Sent(Some(adjustment)) => outcome.adjustments.push(adjustment),
+            Synced::Sent(None) | Synced::Skipped => {}
+        }
+    }
+    tag_uploaded(tagger, &sync_run.to_tag.into_inner().unwrap(), &config.uploaded_tag);
+    outcome
+}
+
+/// Whether an interval is part of the requested report: it starts within the reporting range,
+/// and has all of the requested tags
+pub fn in_report(tw_log: &TimeWarriorLog, config: &Config) -> bool {
+    let (range_start, range_end) = config.range;
+    let in_range = match parse_tw_time(&tw_log.start, &config.timezone) {
+        Ok(start) => range_start.is_none_or(|s| start >= s) && range_end.is_none_or(|e| start < e),
+        Err(_) => range_start.is_none() && range_end.is_none(),
+    };
+    in_range && config.filter_tags.iter().all(|t| tw_log.tags.contains(t))
+}
+
+/// The result of a sync
+#[derive(Debug, Default)]
+pub struct Outcome {
+    /// The rows of the report printed for dry runs and reconciliation
+    pub report: Vec<ReportEntry>,
+    /// Changes made to the worklogs sent to Jira, such as rounding, to let the user know
+    pub adjustments: Vec<String>,
+}
+
+/// Runs a sync of TimeWarrior intervals with Jira: uploads new intervals, updates edited ones,
+/// and marks them as uploaded; or, when reconciling, finds worklogs without a local interval.
+pub async fn run(
+    client: &impl JiraClient,
+    tagger: &impl Tagger,
+    config: &Config,
+    tw_logs: Vec<TimeWarriorLog>,
+    ledger: &Mutex<Ledger>,
+) -> Outcome {
+    let tw_logs: Vec<TimeWarriorLog> = tw_logs.into_iter().filter(|l| in_report(l, config)).collect();
+    // In reconciliation mode, we look for orphaned worklogs in Jira instead of uploading
+    if config.reconcile {
+        let local_logs: Vec<(String, &TimeWarriorLog)> = tw_logs
+            .iter()
+            .filter_map(|l| Some((config.issue_matcher.find_issue(&l.tags)?, l)))
+            .collect();
+        let report = reconcile_worklogs(
+            client,
+            &local_logs,
+            config.range,
+            config.delete_orphans,
+            config.dry_run,
+            ledger,
+        )
+        .await;
+        return Outcome {
+            report,
+            ..Outcome::default()
+        };
+    }
+
+    let selection = select_pending_logs(tw_logs, config, &ledger.lock().unwrap());
+    if !config.dry_run {
+        // An interval can be in the ledger but untagged if tagging failed after the upload
+        tag_uploaded(tagger, &selection.untagged, &config.uploaded_tag);
+    }
+    // Group pending logs into the worklogs we need to upload or update
+    let pending_worklogs = group_pending_logs(
+        selection.pending,
+        config.aggregation,
+        &config.timezone,
+        &ledger.lock().unwrap(),
+    );
+    let planned = build_worklogs(
+        pending_worklogs,
+        &selection.annotations,
+        config,
+        client.connection(),
+    );
+    let mut outcome = sync_worklogs(client, tagger, config, planned, ledger).await;
+    outcome.report.splice(0..0, selection.skipped);
+    outcome
+}
@@ -0,0 +1,307 @@
+use crate::config::*;
+use crate::tests::util::{from_tw_config, temp_path, tw_config};
+use crate::timezone::Timezone;
+use log::LevelFilter;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+// Utility functions
+fn errors(pairs: &[(&str, &str)]) -> Vec<String> {
+    from_tw_config(&tw_config(pairs)).unwrap_err()
+}
+
+#[test]
+fn config_defaults() {
+    let c = from_tw_config(&tw_config(&[])).unwrap();
+    assert_eq!(c.url, "https://myjira.atlassian.net");
+    assert_eq!(c.user, "user@myjira.com");
+    assert_eq!(c.token, "secret");
+    assert!(!c.is_pat);
+    assert_eq!(c.api_version, "latest");
+    assert_eq!(c.log_level, LevelFilter::Error);
+    assert!(c.skip_existing);
+    assert_eq!(c.uploaded_tag, "jira-uploaded");
+    assert_eq!(c.timezone, Timezone::default());
+    assert_eq!(c.aggregation, Aggregation::None);
+    assert!(!c.reconcile);
+    assert!(!c.delete_orphans);
+    assert!(!c.dry_run);
+    assert!(!c.check);
+    assert_eq!(c.max_concurrency, 4);
+}
+
+#[test]
+fn config_debug_masks_token() {
+    let c = from_tw_config(&tw_config(&[])).unwrap();
+    let debug = format!("{:?}", c);
+    assert!(!debug.contains("secret"), "{}", debug);
+    assert!(debug.contains("token: \"<redacted>\""));
+}
+
+#[test]
+fn config_cloud_flavor() {
+    let c = from_tw_config(&tw_config(&[("twjp.api_flavor", "cloud")])).unwrap();
+    assert_eq!(c.flavor, crate::jira::ApiFlavor::Cloud);
+    assert_eq!(c.api_version, "3");
+    let c = from_tw_config(&tw_config(&[
+        ("twjp.api_flavor", "cloud"),
+        ("twjp.api_version", "2"),
+    ]))
+    .unwrap();
+    assert_eq!(c.api_version, "2");
+}
+
+#[test]
+fn config_oauth() {
+    let mut tw_conf = tw_config(&[
+        ("twjp.oauth.client_id", "client"),
+        ("twjp.oauth.client_secret", "client-secret"),
+        ("temp.db", "/home/user/.timewarrior"),
+    ]);
+    tw_conf.remove("twjp.user");
+    tw_conf.remove("twjp.token");
+    let c = from_tw_config(&tw_conf).unwrap();
+    let oauth = c.oauth.unwrap();
+    assert_eq!(oauth.client_id, "client");
+    assert_eq!(oauth.port, 8765);
+    assert_eq!(oauth.token_url, crate::oauth::TOKEN_URL);
+    assert_eq!(
+        oauth.token_path,
+        std::path::PathBuf::from("/home/user/.timewarrior/jirapush-oauth.json")
+    );
+    // OAuth is only available on Jira Cloud
+    assert_eq!(c.flavor, crate::jira::ApiFlavor::Cloud);
+    tw_conf.insert("twjp.api_flavor".to_string(), "server".to_string());
+    assert_eq!(
+        from_tw_config(&tw_conf).unwrap_err(),
+        vec!["twjp.oauth.client_id requires twjp.api_flavor to be cloud"]
+    );
+}
+
+#[test]
+fn config_login_requires_oauth() {
+    assert_eq!(
+        errors(&[("temp.report.tags", "login")]),
+        vec!["Logging in requires twjp.oauth.client_id to be configured"]
+    );
+}
+
+#[test]
+fn config_dry_run_command() {
+    let c = from_tw_config(&tw_config(&[("temp.report.tags", "dry-run")])).unwrap();
+    assert!(c.dry_run);
+    assert!(c.filter_tags.is_empty());
+}
+
+#[test]
+fn config_check_command() {
+    let c = from_tw_config(&tw_config(&[("temp.report.tags", "check")])).unwrap();
+    assert!(c.check);
+}
+
+#[test]
+fn config_report_range_and_filter() {
+    let c = from_tw_config(&tw_config(&[
+        ("temp.report.start", "20210101T000000Z"),
+        ("temp.report.end", ""),
+        ("temp.report.tags", "check,billable"),
+    ]))
+    .unwrap();
+    assert_eq!(c.range.0.map(|s| s.unix_timestamp()), Some(1609459200));
+    assert_eq!(c.range.1, None);
+    // The command tag isn't a filter
+    assert_eq!(c.filter_tags, vec!["billable"]);
+    assert_eq!(
+        errors(&[("temp.report.tags", "billable"), ("twjp.reconcile", "true")]),
+        vec!["twjp.reconcile can't be combined with filtering by tags"]
+    );
+}
+
+#[test]
+fn config_rounding() {
+    use crate::rounding::{RoundingMode, RoundingPolicy};
+    let c = from_tw_config(&tw_config(&[
+        ("twjp.rounding.mode", "up"),
+        ("twjp.rounding.increment", "15"),
+        ("twjp.rounding.minimum", "30"),
+    ]))
+    .unwrap();
+    assert_eq!(
+        c.rounding,
+        RoundingPolicy {
+            mode: RoundingMode::Up,
+            increment: 900,
+            minimum: 1800,
+        }
+    );
+    assert_eq!(from_tw_config(&tw_config(&[])).unwrap().rounding, RoundingPolicy::default());
+}
+
+#[test]
+fn config_invalid_rounding() {
+    assert_eq!(
+        errors(&[
+            ("twjp.rounding.mode", "sideways"),
+            ("twjp.rounding.increment", "0"),
+            ("twjp.rounding.minimum", "-5"),
+        ]),
+        vec![
+            "Invalid value \"sideways\" for twjp.rounding.mode: expected one of none, nearest, up, or down",
+            "Invalid value \"0\" for twjp.rounding.increment: expected a number of minutes, at least 1",
+            "Invalid value \"-5\" for twjp.rounding.minimum: expected a number of minutes, at least 0",
+        ]
+    );
+}
+
+#[test]
+fn config_values() {
+    let c = from_tw_config(&tw_config(&[
+        ("twjp.is_pat", "yes"),
+        ("twjp.skip_existing", "off"),
+        ("twjp.timezone", "Europe/Berlin"),
+        ("twjp.aggregate", "daily"),
+        ("twjp.dry_run", "true"),
+    ]))
+    .unwrap();
+    assert!(c.is_pat);
+    assert!(!c.skip_existing);
+    assert_eq!(c.timezone, Timezone::from_str("Europe/Berlin").unwrap());
+    assert_eq!(c.aggregation, Aggregation::Daily);
+    assert!(c.dry_run);
+}
+
+#[test]
+fn config_missing_required() {
+    let errors = from_tw_config(&HashMap::new()).unwrap_err();
+    assert_eq!(
+        errors,
+        vec![
+            "Missing required config twjp.url",
+            "Missing required config twjp.user",
+            "Missing required config twjp.token",
+        ]
+    );
+}
+
+#[test]
+fn config_token_command() {
+    let mut tw_conf = tw_config(&[("twjp.token_command", "printf 'from-command\\nlogin: me'")]);
+    tw_conf.remove("twjp.token");
+    assert_eq!(from_tw_config(&tw_conf).unwrap().token, "from-command");
+
+    tw_conf.insert("twjp.token_command".to_string(), "echo oops >&2; exit 3".to_string());
+    let errors = from_tw_config(&tw_conf).unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].ends_with("failed (exit status: 3): oops"), "{}", errors[0]);
+}
+
+#[test]
+fn config_token_file() {
+    let path = temp_path("token");
+    std::fs::write(&path, "from-file\n").unwrap();
+    let mut tw_conf = tw_config(&[("twjp.token_file", path.to_str().unwrap())]);
+    tw_conf.remove("twjp.token");
+    let c = from_tw_config(&tw_conf);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(c.unwrap().token, "from-file");
+}
+
+#[test]
+fn config_token_env() {
+    let env = |token: &str| -> HashMap<String, String> {
+        [(TOKEN_ENV_VAR.to_string(), token.to_string())].iter().cloned().collect()
+    };
+    let path = temp_path("token-env");
+    std::fs::write(&path, "from-file\n").unwrap();
+    let sources = [
+        ("twjp.token", "from-config"),
+        ("twjp.token_command", "echo from-command"),
+        ("twjp.token_file", path.to_str().unwrap()),
+    ];
+    for (key, value) in sources.iter() {
+        let mut tw_conf = tw_config(&[(key, value)]);
+        if *key != "twjp.token" {
+            tw_conf.remove("twjp.token");
+        }
+        // The environment takes precedence over every configured source
+        let c = Config::from_tw_config_with_env(&tw_conf, &env("from-env")).unwrap();
+        assert_eq!(c.token, "from-env", "{}", key);
+        // An empty variable is ignored
+        let c = Config::from_tw_config_with_env(&tw_conf, &env("")).unwrap();
+        assert_ne!(c.token, "", "{}", key);
+        assert_ne!(c.token, "from-env", "{}", key);
+    }
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn config_oauth_client_secret_env() {
+    let env = |secret: &str| -> HashMap<String, String> {
+        [(OAUTH_CLIENT_SECRET_ENV_VAR.to_string(), secret.to_string())].iter().cloned().collect()
+    };
+    let tw_conf = tw_config(&[
+        ("twjp.api_flavor", "cloud"),
+        ("twjp.oauth.client_id", "client"),
+        ("twjp.oauth.client_secret", "from-config"),
+    ]);
+    let secret = |env| Config::from_tw_config_with_env(&tw_conf, &env).unwrap().oauth.unwrap().client_secret;
+    assert_eq!(secret(env("from-env")), "from-env");
+    assert_eq!(secret(env("")), "from-config");
+}
+
+#[test]
+fn config_token_conflicting_sources() {
+    assert_eq!(
+        errors(&[("twjp.token_file", "/tmp/token")]),
+        vec!["Only one of twjp.token, twjp.token_command, and twjp.token_file may be set"]
+    );
+}
+
+#[test]
+fn config_invalid_bool() {
+    assert_eq!(
+        errors(&[("twjp.skip_existing", "flase")]),
+        vec!["Invalid value \"flase\" for twjp.skip_existing: expected on/off, yes/no, true/false or 1/0"]
+    );
+}
+
+#[test]
+fn config_unknown_key() {
+    assert_eq!(
+        errors(&[("twjp.skip_exisitng", "false")]),
+        vec!["Unknown config key twjp.skip_exisitng"]
+    );
+}
+
+#[test]
+fn config_reports_all_errors() {
+    let errors = errors(&[
+        ("twjp.log_level", "loud"),
+        ("twjp.timezone", "Mars/Olympus_Mons"),
+        ("twjp.issue_key_pattern", "[A-Z"),
+        ("twjp.rounding.mode", "sideways"),
+    ]);
+    assert_eq!(errors.len(), 4);
+}
+
+#[test]
+fn config_log_level() {
+    assert_eq!(log_level(&tw_config(&[("verbose", "on")])), LevelFilter::Warn);
+    assert_eq!(
+        log_level(&tw_config(&[("verbose", "on"), ("debug", "on")])),
+        LevelFilter::Debug
+    );
+    assert_eq!(
+        log_level(&tw_config(&[("debug", "on"), ("twjp.log_level", "info")])),
+        LevelFilter::Info
+    );
+    assert_eq!(log_level(&tw_config(&[("twjp.log_level", "loud")])), LevelFilter::Error);
+}
+
+#[test]
+fn config_invalid_concurrency() {
+    assert_eq!(
+        errors(&[("twjp.max_concurrency", "0")]),
+        vec!["Invalid value \"0\" for twjp.max_concurrency: expected a positive number"]
+    );
+}
@@ -0,0 +1,544 @@
+use crate::jira::*;
+use std::time::Duration;
+use time::OffsetDateTime;
+
+// Utility functions
+fn tags(tags: &[&str]) -> Vec<String> {
+    tags.iter().map(|t| t.to_string()).collect()
+}
+
+fn default_matcher(project_keys: &[&str]) -> IssueMatcher {
+    IssueMatcher::new(DEFAULT_ISSUE_KEY_PATTERN, tags(project_keys)).unwrap()
+}
+
+#[test]
+fn find_issue_url() {
+    let m = default_matcher(&[]);
+    assert_eq!(
+        m.find_issue(&tags(&[
+            "ISSUE",
+            "(bw)Is#2 - Example 2 .. https://myjira.atlassian.net/browse/ISSUE-2"
+        ])),
+        Some("ISSUE-2".to_string())
+    );
+}
+
+#[test]
+fn find_issue_bare_key() {
+    let m = default_matcher(&[]);
+    assert_eq!(
+        m.find_issue(&tags(&["meeting", "PROJ-123"])),
+        Some("PROJ-123".to_string())
+    );
+}
+
+#[test]
+fn find_issue_prefers_url() {
+    let m = default_matcher(&[]);
+    assert_eq!(
+        m.find_issue(&tags(&["PROJ-1", "https://myjira.atlassian.net/browse/ISSUE-2"])),
+        Some("ISSUE-2".to_string())
+    );
+}
+
+#[test]
+fn find_issue_rejects_partial_key() {
+    let m = default_matcher(&[]);
+    assert_eq!(m.find_issue(&tags(&["fix PROJ-123", "proj-123", "ISSUE"])), None);
+}
+
+#[test]
+fn find_issue_project_allow_list() {
+    let m = default_matcher(&["PROJ", "OPS"]);
+    assert_eq!(
+        m.find_issue(&tags(&["UTF-8", "OPS-7"])),
+        Some("OPS-7".to_string())
+    );
+    assert_eq!(m.find_issue(&tags(&["UTF-8"])), None);
+}
+
+#[test]
+fn find_issue_custom_pattern() {
+    let m = IssueMatcher::new(r"[a-z]+-\d+", vec![]).unwrap();
+    assert_eq!(
+        m.find_issue(&tags(&["PROJ-1", "proj-1"])),
+        Some("proj-1".to_string())
+    );
+}
+
+fn connection(api_version: &str) -> JiraConnection {
+    JiraConnection {
+        user: "user@myjira.com".to_string(),
+        token: "secret".to_string(),
+        is_pat: false,
+        instance_url: "https://myjira.atlassian.net".to_string(),
+        api_version: api_version.to_string(),
+        flavor: ApiFlavor::Server,
+        account_id: None,
+        retry: RetryPolicy::default(),
+        oauth: None,
+    }
+}
+
+#[test]
+fn worklog_comment_plain_text() {
+    let c = WorklogComment::new(&connection("latest"), "Reviewed the pull request");
+    assert_eq!(c, WorklogComment::Text("Reviewed the pull request".to_string()));
+    assert_eq!(
+        serde_json::to_value(&c).unwrap(),
+        serde_json::json!("Reviewed the pull request")
+    );
+}
+
+#[test]
+fn worklog_comment_adf() {
+    let c = WorklogComment::new(&connection("3"), "Reviewed\n\nthe pull request");
+    assert_eq!(
+        serde_json::to_value(&c).unwrap(),
+        serde_json::json!({
+            "type": "doc",
+            "version": 1,
+            "content": [
+                { "type": "paragraph", "content": [{ "type": "text", "text": "Reviewed" }] },
+                { "type": "paragraph", "content": [{ "type": "text", "text": "the pull request" }] },
+            ],
+        })
+    );
+}
+
+#[test]
+fn worklog_optional_fields_omitted() {
+    let wl = JiraWorklog {
+        id: None,
+        started: "2021-01-01T00:00:00.000+0000".to_string(),
+        time_spent_seconds: 60,
+        author: Author {
+            name: Some("user@myjira.com".to_string()),
+            account_id: None,
+        },
+        comment: None,
+    };
+    let v = serde_json::to_value(&wl).unwrap();
+    assert!(v.get("id").is_none());
+    assert!(v.get("comment").is_none());
+}
+
+#[test]
+fn retry_backoff_grows_exponentially() {
+    let policy = RetryPolicy {
+        max_retries: 5,
+        base_delay: Duration::from_millis(1000),
+        max_delay: Duration::from_secs(5),
+    };
+    for _ in 0..100 {
+        let first = policy.backoff(1);
+        assert!(first >= Duration::from_millis(500) && first <= Duration::from_millis(1000));
+        let third = policy.backoff(3);
+        assert!(third >= Duration::from_millis(2000) && third <= Duration::from_millis(4000));
+        // Capped at the maximum delay
+        let tenth = policy.backoff(10);
+        assert!(tenth >= Duration::from_millis(2500) && tenth <= Duration::from_secs(5));
+    }
+}
+
+#[test]
+fn retry_after_seconds() {
+    assert_eq!(
+        parse_retry_after("120", OffsetDateTime::now_utc()),
+        Some(Duration::from_secs(120))
+    );
+}
+
+#[test]
+fn retry_after_http_date() {
+    let now = OffsetDateTime::parse("2015-10-21T07:27:00+0000", "%FT%H:%M:%S%z").unwrap();
+    assert_eq!(
+        parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT", now),
+        Some(Duration::from_secs(60))
+    );
+    // Dates in the past mean we can retry right away
+    assert_eq!(
+        parse_retry_after("Wed, 21 Oct 2015 07:00:00 GMT", now),
+        Some(Duration::from_secs(0))
+    );
+    assert_eq!(parse_retry_after("soon", now), None);
+}
+
+#[test]
+fn jira_error_messages() {
+    let auth = JiraError::Authentication(reqwest::StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        auth.to_string(),
+        "Authentication failed (401 Unauthorized), check your user and token"
+    );
+    let status = JiraError::Status(reqwest::StatusCode::BAD_REQUEST, "bad".to_string());
+    assert_eq!(
+        status.to_string(),
+        "Unexpected status 400 Bad Request: \"bad\""
+    );
+}
+
+#[test]
+fn connection_debug_masks_token() {
+    let jc = connection("latest");
+    let debug = format!("{:?}", jc);
+    assert!(!debug.contains(&jc.token), "{}", debug);
+    assert!(debug.contains("<redacted>"));
+}
+
+#[test]
+fn redact_headers_masks_credentials() {
+    let mut headers = reqwest::header::HeaderMap::new();
+    headers.insert(reqwest::header::AUTHORIZATION, "Bearer secret".parse().unwrap());
+    headers.insert(reqwest::header::ACCEPT, "application/json".parse().unwrap());
+    let redacted = redact_headers(&headers);
+    assert!(redacted.contains(&("authorization".to_string(), "<redacted>".to_string())));
+    assert!(redacted.contains(&("accept".to_string(), "application/json".to_string())));
+}
+
+#[test]
+fn author_identity_server() {
+    let jc = connection("latest");
+    let author = jc.author();
+    assert_eq!(
+        serde_json::to_value(&author).unwrap(),
+        serde_json::json!({ "name": "user@myjira.com" })
+    );
+    assert!(jc.is_author(&author));
+    let other: Author = serde_json::from_str(r#"{ "name": "someone-else" }"#).unwrap();
+    assert!(!jc.is_author(&other));
+}
+
+#[test]
+fn author_identity_cloud() {
+    let mut jc = connection("3");
+    jc.flavor = ApiFlavor::Cloud;
+    jc.account_id = Some("5b10ac8d82e05b22cc7d4ef5".to_string());
+    assert_eq!(
+        serde_json::to_value(jc.author()).unwrap(),
+        serde_json::json!({ "accountId": "5b10ac8d82e05b22cc7d4ef5" })
+    );
+    // Cloud no longer returns names, so only the accountId identifies us
+    let ours: Author = serde_json::from_str(
+        r#"{ "accountId": "5b10ac8d82e05b22cc7d4ef5", "displayName": "User" }"#,
+    )
+    .unwrap();
+    let other: Author = serde_json::from_str(r#"{ "accountId": "557058:f58131cb" }"#).unwrap();
+    assert!(jc.is_author(&ours));
+    assert!(!jc.is_author(&other));
+}
+
+// Requests against a mock Jira server
+use crate::tests::mock_jira::{MockJira, MockResponse};
+
+fn mock_connection(server: &MockJira) -> JiraConnection {
+    let mut jc = connection("2");
+    jc.instance_url = server.url();
+    // Don't keep tests waiting on retries
+    jc.retry = RetryPolicy {
+        max_retries: 1,
+        base_delay: Duration::from_millis(1),
+        max_delay: Duration::from_millis(10),
+    };
+    jc
+}
+
+fn worklog(seconds: i64) -> JiraWorklog {
+    JiraWorklog {
+        id: None,
+        started: "2021-01-01T09:00:00.000+0000".to_string(),
+        time_spent_seconds: seconds,
+        author: Author {
+            name: Some("user@myjira.com".to_string()),
+            account_id: None,
+        },
+        comment: None,
+    }
+}
+
+fn worklog_page(start_at: usize, total: usize, ids: &[&str]) -> serde_json::Value {
+    let worklogs: Vec<serde_json::Value> = ids
+        .iter()
+        .map(|id| {
+            serde_json::json!({
+                "id": id,
+                "started": "2021-01-01T09:00:00.000+0000",
+                "timeSpentSeconds": 3600,
+                "author": { "name": "user@myjira.com", "displayName": "User" },
+            })
+        })
+        .collect();
+    serde_json::json!({ "startAt": start_at, "maxResults": ids.len(), "total": total, "worklogs": worklogs })
+}
+
+#[tokio::test]
+async fn get_worklogs_basic_auth() {
+    let server = MockJira::start(|_| MockResponse::json(200, worklog_page(0, 1, &["10"]))).await;
+    let worklogs = get_worklogs(&reqwest::Client::new(), &mock_connection(&server), "PROJ-1", None)
+        .await
+        .unwrap();
+    assert_eq!(worklogs.len(), 1);
+    assert_eq!(worklogs[0].id.as_deref(), Some("10"));
+    assert_eq!(worklogs[0].author.name.as_deref(), Some("user@myjira.com"));
+
+    let requests = server.requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].method, "GET");
+    assert!(requests[0].path.starts_with("/rest/api/2/issue/PROJ-1/worklog?"));
+    // base64 of user@myjira.com:secret
+    assert_eq!(
+        requests[0].headers["authorization"],
+        "Basic dXNlckBteWppcmEuY29tOnNlY3JldA=="
+    );
+    assert_eq!(requests[0].headers["accept"], "application/json");
+}
+
+#[tokio::test]
+async fn get_worklogs_pat_auth() {
+    let server = MockJira::start(|_| MockResponse::json(200, worklog_page(0, 0, &[]))).await;
+    let mut jc = mock_connection(&server);
+    jc.is_pat = true;
+    get_worklogs(&reqwest::Client::new(), &jc, "PROJ-1", None)
+        .await
+        .unwrap();
+    assert_eq!(server.requests()[0].headers["authorization"], "Bearer secret");
+}
+
+#[tokio::test]
+async fn get_worklogs_follows_pagination() {
+    let server = MockJira::start(|r| match r.query("startAt").as_deref() {
+        Some("0") => MockResponse::json(200, worklog_page(0, 3, &["1", "2"])),
+        _ => MockResponse::json(200, worklog_page(2, 3, &["3"])),
+    })
+    .await;
+    let started_after = OffsetDateTime::parse("2021-01-01T00:00:00+0000", "%FT%H:%M:%S%z").unwrap();
+    let worklogs = get_worklogs(
+        &reqwest::Client::new(),
+        &mock_connection(&server),
+        "PROJ-1",
+        Some(started_after),
+    )
+    .await
+    .unwrap();
+    let ids: Vec<&str> = worklogs.iter().filter_map(|w| w.id.as_deref()).collect();
+    assert_eq!(ids, vec!["1", "2", "3"]);
+
+    let requests = server.requests();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[1].query("startAt").as_deref(), Some("2"));
+    assert_eq!(requests[1].query("startedAfter").as_deref(), Some("1609459200000"));
+}
+
+#[tokio::test]
+async fn get_worklogs_stops_on_empty_page() {
+    // The total may be outdated if worklogs were deleted while paging
+    let server = MockJira::start(|r| match r.query("startAt").as_deref() {
+        Some("0") => MockResponse::json(200, worklog_page(0, 5, &["1", "2"])),
+        _ => MockResponse::json(200, worklog_page(2, 5, &[])),
+    })
+    .await;
+    let worklogs = get_worklogs(&reqwest::Client::new(), &mock_connection(&server), "PROJ-1", None)
+        .await
+        .unwrap();
+    assert_eq!(worklogs.len(), 2);
+    assert_eq!(server.requests().len(), 2);
+    assert_eq!(server.requests()[0].query("startedAfter"), None);
+}
+
+#[tokio::test]
+async fn upload_worklog_rounds_up_short_worklogs() {
+    let server = MockJira::start(|_| MockResponse::json(201, serde_json::json!({ "id": "42" }))).await;
+    let id = upload_worklog(&reqwest::Client::new(), &mock_connection(&server), "PROJ-1", &worklog(30))
+        .await
+        .unwrap();
+    assert_eq!(id, "42");
+
+    let requests = server.requests();
+    assert_eq!(requests[0].method, "POST");
+    assert_eq!(requests[0].path, "/rest/api/2/issue/PROJ-1/worklog");
+    assert_eq!(requests[0].headers["content-type"], "application/json");
+    let body = requests[0].json();
+    assert_eq!(body["timeSpentSeconds"], 60);
+    assert_eq!(body["started"], "2021-01-01T09:00:00.000+0000");
+    assert!(body.get("id").is_none());
+}
+
+#[tokio::test]
+async fn update_and_delete_worklog() {
+    let server = MockJira::start(|r| match r.method.as_str() {
+        "PUT" => MockResponse::json(200, serde_json::json!({ "id": "42" })),
+        _ => MockResponse::raw(204, ""),
+    })
+    .await;
+    let rc = reqwest::Client::new();
+    let jc = mock_connection(&server);
+    update_worklog(&rc, &jc, "PROJ-1", "42", &worklog(5400))
+        .await
+        .unwrap();
+    delete_worklog(&rc, &jc, "PROJ-1", "42").await.unwrap();
+
+    let requests = server.requests();
+    assert_eq!(requests[0].method, "PUT");
+    assert_eq!(requests[0].path, "/rest/api/2/issue/PROJ-1/worklog/42");
+    assert_eq!(requests[0].json()["timeSpentSeconds"], 5400);
+    assert_eq!(requests[1].method, "DELETE");
+    assert_eq!(requests[1].path, "/rest/api/2/issue/PROJ-1/worklog/42");
+}
+
+#[tokio::test]
+async fn error_statuses() {
+    let server = MockJira::start(|r| match r.path.as_str() {
+        p if p.contains("UNAUTHORIZED-1") => MockResponse::raw(401, ""),
+        p if p.contains("FORBIDDEN-1") => MockResponse::raw(403, ""),
+        p if p.contains("CAPTCHA-1") => MockResponse::raw(403, "")
+            .header("X-Authentication-Denied-Reason", "CAPTCHA_CHALLENGE; login-url=https://jira/login.jsp"),
+        p if p.contains("MISSING-1") => MockResponse::raw(404, r#"{"errorMessages":["Issue does not exist"]}"#),
+        _ => MockResponse::raw(500, "boom"),
+    })
+    .await;
+    let rc = reqwest::Client::new();
+    let jc = mock_connection(&server);
+    let upload = |issue: &'static str| {
+        let (rc, jc) = (rc.clone(), jc.clone());
+        async move { upload_worklog(&rc, &jc, issue, &worklog(60)).await }
+    };
+    assert!(matches!(
+        upload("UNAUTHORIZED-1").await,
+        Err(JiraError::Authentication(reqwest::StatusCode::UNAUTHORIZED))
+    ));
+    assert!(matches!(
+        upload("FORBIDDEN-1").await,
+        Err(JiraError::Forbidden)
+    ));
+    assert!(matches!(
+        upload("CAPTCHA-1").await,
+        Err(JiraError::Authentication(reqwest::StatusCode::FORBIDDEN))
+    ));
+    assert!(matches!(upload("MISSING-1").await, Err(JiraError::NotFound)));
+    match upload("BROKEN-1").await {
+        Err(JiraError::Status(status, body)) => {
+            assert_eq!(status, reqwest::StatusCode::INTERNAL_SERVER_ERROR);
+            assert_eq!(body, "boom");
+        }
+        r => panic!("unexpected result {:?}", r),
+    }
+    // None of these are retried
+    assert_eq!(server.requests().len(), 5);
+}
+
+#[tokio::test]
+async fn throttling_is_retried() {
+    let server = MockJira::start(|_| MockResponse::raw(429, "").header("Retry-After", "0")).await;
+    let result = get_worklogs(&reqwest::Client::new(), &mock_connection(&server), "PROJ-1", None).await;
+    assert!(matches!(result, Err(JiraError::RateLimited)));
+    // The first attempt and one retry
+    assert_eq!(server.requests().len(), 2);
+}
+
+#[tokio::test]
+async fn unavailable_server_recovers() {
+    let server = MockJira::start(|_| MockResponse::raw(503, "")).await;
+    let jc = mock_connection(&server);
+    let result = delete_worklog(&reqwest::Client::new(), &jc, "PROJ-1", "42").await;
+    assert!(matches!(result, Err(JiraError::Status(reqwest::StatusCode::SERVICE_UNAVAILABLE, _))));
+    assert_eq!(server.requests().len(), 2);
+}
+
+#[tokio::test]
+async fn timed_out_upload_is_not_resent() {
+    // Jira saves the worklog, but responds too late
+    let server = MockJira::start(|_| {
+        MockResponse::json(201, serde_json::json!({ "id": "10001" })).delay(Duration::from_millis(500))
+    })
+    .await;
+    let rc = reqwest::Client::builder()
+        .timeout(Duration::from_millis(100))
+        .build()
+        .unwrap();
+    let jc = mock_connection(&server);
+    let result = upload_worklog(&rc, &jc, "PROJ-1", &worklog(60)).await;
+    assert!(matches!(result, Err(JiraError::Connection(_))));
+    assert_eq!(server.requests().len(), 1);
+    // Reading is safe to retry
+    let _ = get_worklogs(&rc, &jc, "PROJ-1", None).await;
+    assert_eq!(server.requests().len(), 3);
+}
+
+#[tokio::test]
+async fn unavailable_upload_is_not_resent() {
+    let server = MockJira::start(|_| MockResponse::raw(503, "")).await;
+    let jc = mock_connection(&server);
+    let result = upload_worklog(&reqwest::Client::new(), &jc, "PROJ-1", &worklog(60)).await;
+    assert!(matches!(result, Err(JiraError::Status(reqwest::StatusCode::SERVICE_UNAVAILABLE, _))));
+    assert_eq!(server.requests().len(), 1);
+}
+
+#[tokio::test]
+async fn throttled_upload_is_retried() {
+    let server = MockJira::start(|_| MockResponse::raw(429, "").header("Retry-After", "0")).await;
+    let jc = mock_connection(&server);
+    let result = upload_worklog(&reqwest::Client::new(), &jc, "PROJ-1", &worklog(60)).await;
+    assert!(matches!(result, Err(JiraError::RateLimited)));
+    assert_eq!(server.requests().len(), 2);
+}
+
+#[tokio::test]
+async fn malformed_json() {
+    let server = MockJira::start(|r| match r.method.as_str() {
+        "GET" => MockResponse::raw(200, r#"{"worklogs": [{"id": "1""#),
+        _ => MockResponse::raw(201, "<html>Login</html>"),
+    })
+    .await;
+    let rc = reqwest::Client::new();
+    let jc = mock_connection(&server);
+    assert!(matches!(
+        get_worklogs(&rc, &jc, "PROJ-1", None).await,
+        Err(JiraError::Parse(_))
+    ));
+    assert!(matches!(
+        upload_worklog(&rc, &jc, "PROJ-1", &worklog(60)).await,
+        Err(JiraError::Parse(_))
+    ));
+}
+
+#[tokio::test]
+async fn connection_refused() {
+    let mut jc = connection("2");
+    // Nothing listens on the discard port
+    jc.instance_url = "http://127.0.0.1:9".to_string();
+    jc.retry.max_retries = 0;
+    let result = get_worklogs(&reqwest::Client::new(), &jc, "PROJ-1", None).await;
+    assert!(matches!(result, Err(JiraError::Connection(_))));
+}
+
+#[tokio::test]
+async fn get_myself_account_id() {
+    let server = MockJira::start(|_| {
+        MockResponse::json(
+            200,
+            serde_json::json!({ "accountId": "5b10ac8d82e05b22cc7d4ef5", "displayName": "User", "active": true }),
+        )
+    })
+    .await;
+    let user = get_myself(&reqwest::Client::new(), &mock_connection(&server))
+        .await
+        .unwrap();
+    assert_eq!(user.account_id.as_deref(), Some("5b10ac8d82e05b22cc7d4ef5"));
+    assert_eq!(user.name, None);
+    assert_eq!(server.requests()[0].path, "/rest/api/latest/myself");
+}
+
+#[tokio::test]
+async fn check_connection_results() {
+    let server = MockJira::start(|r| match r.path.as_str() {
+        "/rest/api/latest/myself" => MockResponse::json(200, serde_json::json!({ "name": "user" })),
+        _ => MockResponse::json(200, serde_json::json!({ "version": "9.4.0", "deploymentType": "Server" })),
+    })
+    .await;
+    assert!(check_connection(&reqwest::Client::new(), &mock_connection(&server)).await);
+
+    let server = MockJira::start(|_| MockResponse::raw(401, "")).await;
+    assert!(!check_connection(&reqwest::Client::new(), &mock_connection(&server)).await);
+    // The server info isn't fetched once the credentials are rejected
+    assert_eq!(server.requests().len(), 1);
+}
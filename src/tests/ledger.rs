@@ -0,0 +1,120 @@
+use crate::ledger::*;
+use crate::tests::util::temp_path;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+// Utility functions
+fn entry(start: &str, worklog_id: &str) -> LedgerEntry {
+    LedgerEntry {
+        start: start.to_string(),
+        end: "20210102T000000Z".to_string(),
+        issue: "ISSUE-1".to_string(),
+        worklog_id: worklog_id.to_string(),
+    }
+}
+
+#[test]
+fn load_missing_ledger() {
+    let path = temp_path("missing");
+    let ledger = Ledger::load(&path).unwrap();
+    assert_eq!(ledger.find("20210101T000000Z", "20210102T000000Z", "ISSUE-1"), None);
+}
+
+#[test]
+fn load_invalid_ledger() {
+    let path = temp_path("invalid");
+    std::fs::write(&path, "not json").unwrap();
+    assert!(Ledger::load(&path).is_err());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn save_and_reload_ledger() {
+    let path = temp_path("roundtrip");
+    let mut ledger = Ledger::load(&path).unwrap();
+    ledger.record(entry("20210101T000000Z", "10001"));
+    ledger.save().unwrap();
+
+    let ledger = Ledger::load(&path).unwrap();
+    assert_eq!(
+        ledger.find("20210101T000000Z", "20210102T000000Z", "ISSUE-1"),
+        Some(&entry("20210101T000000Z", "10001"))
+    );
+    assert_eq!(ledger.find("20210101T000000Z", "20210102T000000Z", "ISSUE-2"), None);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn record_replaces_existing_entry() {
+    let path = temp_path("replace");
+    let mut ledger = Ledger::load(&path).unwrap();
+    ledger.record(entry("20210101T000000Z", "10001"));
+    ledger.record(entry("20210101T000000Z", "10002"));
+    assert_eq!(
+        ledger
+            .find("20210101T000000Z", "20210102T000000Z", "ISSUE-1")
+            .map(|e| e.worklog_id.as_str()),
+        Some("10002")
+    );
+}
+
+#[test]
+fn ledger_path_from_config() {
+    let mut tw_conf = HashMap::new();
+    tw_conf.insert("temp.db".to_string(), "/home/user/.timewarrior".to_string());
+    assert_eq!(
+        ledger_path(&tw_conf),
+        PathBuf::from("/home/user/.timewarrior/jirapush.json")
+    );
+    tw_conf.insert("twjp.ledger".to_string(), "/tmp/ledger.json".to_string());
+    assert_eq!(ledger_path(&tw_conf), PathBuf::from("/tmp/ledger.json"));
+}
+
+#[test]
+fn find_edited_entry() {
+    let path = temp_path("edited");
+    let mut ledger = Ledger::load(&path).unwrap();
+    ledger.record(entry("20210101T000000Z", "10001"));
+    // Lengthened interval
+    assert_eq!(
+        ledger
+            .find_edited("20210101T000000Z", "20210102T010000Z", "ISSUE-1")
+            .map(|e| e.worklog_id.as_str()),
+        Some("10001")
+    );
+    // Moved start
+    assert_eq!(
+        ledger
+            .find_edited("20210101T010000Z", "20210102T000000Z", "ISSUE-1")
+            .map(|e| e.worklog_id.as_str()),
+        Some("10001")
+    );
+    // Unchanged, unrelated, or on another issue
+    assert_eq!(ledger.find_edited("20210101T000000Z", "20210102T000000Z", "ISSUE-1"), None);
+    assert_eq!(ledger.find_edited("20210103T000000Z", "20210104T000000Z", "ISSUE-1"), None);
+    assert_eq!(ledger.find_edited("20210101T000000Z", "20210102T010000Z", "ISSUE-2"), None);
+}
+
+#[test]
+fn find_and_remove_worklog_entries() {
+    let path = temp_path("worklog");
+    let mut ledger = Ledger::load(&path).unwrap();
+    ledger.record(entry("20210101T000000Z", "10001"));
+    ledger.record(entry("20210101T010000Z", "10001"));
+    ledger.record(entry("20210101T020000Z", "10002"));
+    assert_eq!(ledger.find_worklog("10001").len(), 2);
+    ledger.remove(&entry("20210101T000000Z", "10001"));
+    assert_eq!(
+        ledger.find_worklog("10001"),
+        vec![&entry("20210101T010000Z", "10001")]
+    );
+}
+
+#[test]
+fn forget_worklog() {
+    let path = temp_path("forget");
+    let mut ledger = Ledger::load(&path).unwrap();
+    ledger.record(entry("20210101T000000Z", "10001"));
+    ledger.forget("10001");
+    assert_eq!(ledger.find("20210101T000000Z", "20210102T000000Z", "ISSUE-1"), None);
+}
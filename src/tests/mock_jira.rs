@@ -0,0 +1,169 @@
+//! A minimal in-process stand-in for a Jira server, recording the requests it receives
+//! and answering them with canned responses.
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// A request received by the mock server
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub method: String,
+    /// The path including the query string
+    pub path: String,
+    /// Headers, with lowercase names
+    pub headers: HashMap<String, String>,
+    pub body: String,
+}
+
+impl RecordedRequest {
+    /// The value of a query parameter, if present
+    pub fn query(&self, name: &str) -> Option<String> {
+        reqwest::Url::parse(&format!("http://localhost{}", self.path))
+            .ok()?
+            .query_pairs()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.to_string())
+    }
+
+    /// The request body parsed as JSON
+    pub fn json(&self) -> serde_json::Value {
+        serde_json::from_str(&self.body).unwrap()
+    }
+}
+
+/// A canned response
+pub struct MockResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+    /// How long to wait before responding
+    pub delay: Option<std::time::Duration>,
+}
+
+impl MockResponse {
+    pub fn json(status: u16, body: serde_json::Value) -> MockResponse {
+        MockResponse::raw(status, &body.to_string())
+    }
+
+    pub fn raw(status: u16, body: &str) -> MockResponse {
+        MockResponse {
+            status,
+            headers: vec![],
+            body: body.to_string(),
+            delay: None,
+        }
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> MockResponse {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn delay(mut self, delay: std::time::Duration) -> MockResponse {
+        self.delay = Some(delay);
+        self
+    }
+}
+
+type Responder = dyn Fn(&RecordedRequest) -> MockResponse + Send + Sync;
+
+/// A mock Jira server listening on a random local port
+pub struct MockJira {
+    url: String,
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+}
+
+impl MockJira {
+    /// Starts a server answering every request with the given responder
+    pub async fn start(
+        responder: impl Fn(&RecordedRequest) -> MockResponse + Send + Sync + 'static,
+    ) -> MockJira {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let responder: Arc<Responder> = Arc::new(responder);
+        let recorded = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let responder = responder.clone();
+                let recorded = recorded.clone();
+                tokio::spawn(handle_request(stream, responder, recorded));
+            }
+        });
+        MockJira { url, requests }
+    }
+
+    pub fn url(&self) -> String {
+        self.url.clone()
+    }
+
+    /// All requests received so far, in order
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+/// Reads a single request from a connection, records it, and answers it
+async fn handle_request(
+    mut stream: TcpStream,
+    responder: Arc<Responder>,
+    recorded: Arc<Mutex<Vec<RecordedRequest>>>,
+) -> Option<()> {
+    let mut buf = Vec::new();
+    let mut chunk = [0; 4096];
+    let header_end = loop {
+        let n = stream.read(&mut chunk).await.ok()?;
+        if n == 0 {
+            return None;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+        if let Some(i) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            break i + 4;
+        }
+    };
+    let head = String::from_utf8_lossy(&buf[..header_end]).to_string();
+    let mut lines = head.lines();
+    let mut request_line = lines.next()?.split_whitespace();
+    let method = request_line.next()?.to_string();
+    let path = request_line.next()?.to_string();
+    let headers: HashMap<String, String> = lines
+        .filter_map(|l| l.split_once(':'))
+        .map(|(k, v)| (k.trim().to_lowercase(), v.trim().to_string()))
+        .collect();
+    let length: usize = headers
+        .get("content-length")
+        .and_then(|l| l.parse().ok())
+        .unwrap_or(0);
+    while buf.len() < header_end + length {
+        let n = stream.read(&mut chunk).await.ok()?;
+        if n == 0 {
+            break;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+    let request = RecordedRequest {
+        method,
+        path,
+        headers,
+        body: String::from_utf8_lossy(&buf[header_end..]).to_string(),
+    };
+
+    let response = responder(&request);
+    recorded.lock().unwrap().push(request);
+    if let Some(delay) = response.delay {
+        tokio::time::sleep(delay).await;
+    }
+    let mut head = format!(
+        "HTTP/1.1 {} Mock\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n",
+        response.status,
+        response.body.len()
+    );
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes()).await.ok()?;
+    stream.write_all(response.body.as_bytes()).await.ok()?;
+    stream.shutdown().await.ok()
+}
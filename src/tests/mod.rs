@@ -1 +1,12 @@
+pub(crate) mod config;
+pub(crate) mod jira;
+pub(crate) mod ledger;
+pub(crate) mod mock_jira;
+pub(crate) mod oauth;
+pub(crate) mod rounding;
+pub(crate) mod sync;
+// The TimeWarrior input tests pass their fixtures by reference
+#[allow(clippy::needless_borrow)]
 pub(crate) mod timewarrior;
+pub(crate) mod timezone;
+pub(crate) mod util;
@@ -0,0 +1,153 @@
+use crate::jira::JiraError;
+use crate::oauth::*;
+use crate::tests::mock_jira::{MockJira, MockResponse};
+use crate::tests::util::temp_path;
+use reqwest::Url;
+use std::path::PathBuf;
+use time::OffsetDateTime;
+
+// Utility functions
+fn oauth_config() -> OAuthConfig {
+    OAuthConfig {
+        client_id: "client".to_string(),
+        client_secret: "client-secret".to_string(),
+        port: 8765,
+        token_path: PathBuf::from("/tmp/jirapush-oauth.json"),
+        token_url: TOKEN_URL.to_string(),
+    }
+}
+
+fn tokens(expires_at: i64) -> OAuthTokens {
+    OAuthTokens {
+        access_token: "access".to_string(),
+        refresh_token: "refresh".to_string(),
+        expires_at,
+        cloud_id: "11223344-a1b2-3b33-c444-def123456789".to_string(),
+    }
+}
+
+#[test]
+fn authorize_url_params() {
+    let url = Url::parse(&oauth_config().authorize_url("xyz")).unwrap();
+    let param = |name: &str| {
+        url.query_pairs()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.to_string())
+    };
+    assert_eq!(url.host_str(), Some("auth.atlassian.com"));
+    assert_eq!(param("client_id").as_deref(), Some("client"));
+    assert_eq!(param("redirect_uri").as_deref(), Some("http://localhost:8765/callback"));
+    assert_eq!(param("state").as_deref(), Some("xyz"));
+    assert!(param("scope").unwrap().contains("offline_access"));
+    assert!(!url.as_str().contains("client-secret"));
+}
+
+#[test]
+fn parse_callback_code() {
+    assert_eq!(
+        parse_callback("GET /callback?state=xyz&code=abc HTTP/1.1", "xyz"),
+        Some(Ok("abc".to_string()))
+    );
+    // Requests for anything else, like a favicon, are ignored
+    assert_eq!(parse_callback("GET /favicon.ico HTTP/1.1", "xyz"), None);
+}
+
+#[test]
+fn parse_callback_rejects_state_mismatch() {
+    assert!(matches!(
+        parse_callback("GET /callback?state=other&code=abc HTTP/1.1", "xyz"),
+        Some(Err(_))
+    ));
+    assert!(matches!(
+        parse_callback("GET /callback?error=access_denied&state=xyz HTTP/1.1", "xyz"),
+        Some(Err(_))
+    ));
+}
+
+#[test]
+fn tokens_expiry() {
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    assert!(!tokens(now + 3600).is_expired(OffsetDateTime::now_utc()));
+    // Tokens about to expire are refreshed ahead of time
+    assert!(tokens(now + 30).is_expired(OffsetDateTime::now_utc()));
+    assert_eq!(
+        tokens(now).api_base_url(),
+        "https://api.atlassian.com/ex/jira/11223344-a1b2-3b33-c444-def123456789"
+    );
+}
+
+#[test]
+fn tokens_round_trip() {
+    let path = temp_path("oauth.json");
+    tokens(1234).save(&path).unwrap();
+    let loaded = OAuthTokens::load(&path);
+    std::fs::remove_file(&path).unwrap();
+    assert!(loaded.unwrap() == tokens(1234));
+}
+
+#[cfg(unix)]
+#[test]
+fn tokens_saved_privately() {
+    use std::os::unix::fs::PermissionsExt;
+    let path = temp_path("oauth-private.json");
+    std::fs::write(&path, "{}").unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+    tokens(1234).save(&path).unwrap();
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(mode & 0o777, 0o600);
+}
+
+#[tokio::test]
+async fn access_token_refreshes_expired_tokens() {
+    let server = MockJira::start(|_| {
+        MockResponse::json(
+            200,
+            serde_json::json!({"access_token": "new-access", "expires_in": 3600, "refresh_token": "rotated"}),
+        )
+    })
+    .await;
+    let path = temp_path("oauth-refresh.json");
+    let mut config = oauth_config();
+    config.token_path = path.clone();
+    config.token_url = format!("{}/oauth/token", server.url());
+    let session = OAuthSession::new(config, tokens(0));
+    let rc = reqwest::Client::new();
+
+    assert_eq!(session.access_token(&rc).await.unwrap(), "new-access");
+    // The refreshed token is used until it expires
+    assert_eq!(session.access_token(&rc).await.unwrap(), "new-access");
+    let stored = OAuthTokens::load(&path);
+    let _ = std::fs::remove_file(&path);
+
+    let requests = server.requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].path, "/oauth/token");
+    let body = requests[0].json();
+    assert_eq!(body["grant_type"], "refresh_token");
+    assert_eq!(body["refresh_token"], "refresh");
+    assert_eq!(body["client_secret"], "client-secret");
+    // Refresh tokens rotate, so the new one must be stored for the next run
+    let stored = stored.unwrap();
+    assert_eq!(stored.access_token, "new-access");
+    assert_eq!(stored.refresh_token, "rotated");
+    assert!(!stored.is_expired(OffsetDateTime::now_utc()));
+}
+
+#[tokio::test]
+async fn access_token_refresh_rejected() {
+    let server = MockJira::start(|_| MockResponse::raw(400, r#"{"error":"invalid_grant"}"#)).await;
+    let path = temp_path("oauth-rejected.json");
+    let mut config = oauth_config();
+    config.token_path = path.clone();
+    config.token_url = format!("{}/oauth/token", server.url());
+    let session = OAuthSession::new(config, tokens(0));
+
+    let result = session.access_token(&reqwest::Client::new()).await;
+    assert!(matches!(
+        result,
+        Err(JiraError::Authentication(reqwest::StatusCode::UNAUTHORIZED))
+    ));
+    // The stored tokens are left alone
+    assert!(!path.exists());
+}
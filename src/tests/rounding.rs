@@ -0,0 +1,49 @@
+use crate::rounding::*;
+
+// Utility functions
+fn policy(mode: RoundingMode, increment_minutes: i64, minimum_minutes: i64) -> RoundingPolicy {
+    RoundingPolicy {
+        mode,
+        increment: increment_minutes * 60,
+        minimum: minimum_minutes * 60,
+    }
+}
+
+#[test]
+fn rounding_none() {
+    let p = RoundingPolicy::default();
+    assert_eq!(p.apply(0), 0);
+    assert_eq!(p.apply(427), 427);
+}
+
+#[test]
+fn rounding_nearest() {
+    let p = policy(RoundingMode::Nearest, 15, 0);
+    assert_eq!(p.apply(7 * 60), 0);
+    assert_eq!(p.apply(7 * 60 + 30), 15 * 60);
+    assert_eq!(p.apply(22 * 60), 15 * 60);
+    assert_eq!(p.apply(23 * 60), 30 * 60);
+}
+
+#[test]
+fn rounding_up() {
+    let p = policy(RoundingMode::Up, 15, 0);
+    assert_eq!(p.apply(0), 0);
+    assert_eq!(p.apply(1), 15 * 60);
+    assert_eq!(p.apply(15 * 60), 15 * 60);
+    assert_eq!(p.apply(15 * 60 + 1), 30 * 60);
+}
+
+#[test]
+fn rounding_down() {
+    let p = policy(RoundingMode::Down, 15, 0);
+    assert_eq!(p.apply(14 * 60), 0);
+    assert_eq!(p.apply(29 * 60 + 59), 15 * 60);
+}
+
+#[test]
+fn rounding_minimum() {
+    let p = policy(RoundingMode::Down, 15, 30);
+    assert_eq!(p.apply(5 * 60), 30 * 60);
+    assert_eq!(p.apply(50 * 60), 45 * 60);
+}
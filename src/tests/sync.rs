@@ -0,0 +1,750 @@
+use crate::config::Aggregation;
+use crate::jira::{JiraClient, JiraConnection, JiraError, JiraWorklog};
+use crate::ledger::{Ledger, LedgerEntry};
+use crate::sync::*;
+use crate::tests::util::{config, temp_ledger};
+use crate::timewarrior::{MemoryTagger, TimeWarriorLog};
+use crate::timezone::Timezone;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::str::FromStr;
+
+// Utility functions
+fn tagged_log(id: usize, start: &str, end: Option<&str>, tags: &[&str]) -> TimeWarriorLog {
+    TimeWarriorLog {
+        id,
+        start: start.to_string(),
+        end: end.map(|e| e.to_string()),
+        tags: tags.iter().map(|t| t.to_string()).collect(),
+        annotation: None,
+    }
+}
+
+fn ledger_entry(start: &str, end: &str, issue: &str, worklog_id: &str) -> LedgerEntry {
+    LedgerEntry {
+        start: start.to_string(),
+        end: end.to_string(),
+        issue: issue.to_string(),
+        worklog_id: worklog_id.to_string(),
+    }
+}
+
+fn pending() -> Vec<(String, TimeWarriorLog, Option<LedgerEntry>)> {
+    vec![
+        ("ISSUE-1".to_string(), tagged_log(4, "20210101T090000Z", Some("20210101T091000Z"), &[]), None),
+        ("ISSUE-2".to_string(), tagged_log(3, "20210101T091000Z", Some("20210101T093000Z"), &[]), None),
+        ("ISSUE-1".to_string(), tagged_log(2, "20210101T093000Z", Some("20210101T100000Z"), &[]), None),
+        ("ISSUE-1".to_string(), tagged_log(1, "20210102T090000Z", Some("20210102T100000Z"), &[]), None),
+    ]
+}
+
+#[test]
+fn jira_time_parsing() {
+    let started = parse_jira_time("2021-01-01T09:00:00.123+0100").unwrap();
+    assert_eq!(started.unix_timestamp(), 1609488000);
+    // Malformed times are errors rather than panics
+    assert!(parse_jira_time("2021-01-01").is_err());
+    assert!(parse_jira_time("").is_err());
+    assert!(parse_jira_time("2021-01-01T09:00:00.12é+0100").is_err());
+}
+
+#[test]
+fn group_without_aggregation() {
+    let worklogs = group_pending_logs(pending(), Aggregation::None, &Timezone::default(), &Ledger::default());
+    assert_eq!(worklogs.len(), 4);
+    assert!(worklogs.iter().all(|w| w.logs.len() == 1 && w.worklog_id().is_none()));
+}
+
+#[test]
+fn group_daily_aggregation() {
+    let worklogs = group_pending_logs(pending(), Aggregation::Daily, &Timezone::default(), &Ledger::default());
+    let ids: Vec<(&str, Vec<usize>)> = worklogs
+        .iter()
+        .map(|w| (w.issue.as_str(), w.logs.iter().map(|l| l.id).collect()))
+        .collect();
+    assert_eq!(
+        ids,
+        vec![("ISSUE-1", vec![4, 2]), ("ISSUE-2", vec![3]), ("ISSUE-1", vec![1])]
+    );
+}
+
+#[test]
+fn group_leaves_out_running_intervals() {
+    let mut pending = pending();
+    pending.push(("ISSUE-1".to_string(), tagged_log(5, "20210102T110000Z", None, &[]), None));
+    let worklogs = group_pending_logs(pending, Aggregation::Daily, &Timezone::default(), &Ledger::default());
+    assert!(worklogs.iter().all(|w| w.logs.iter().all(|l| l.id != 5)));
+}
+
+#[test]
+fn build_leaves_out_running_intervals() {
+    let config = config(&[]);
+    let running = PendingWorklog {
+        issue: "ISSUE-1".to_string(),
+        logs: vec![tagged_log(1, "20210101T090000Z", None, &[])],
+        previous: vec![],
+        unchanged: vec![],
+    };
+    assert!(running.intervals().is_empty());
+    assert!(build_worklogs(vec![running], &HashMap::new(), &config, &config.jira_connection()).is_empty());
+}
+
+#[test]
+fn group_daily_aggregation_local_day() {
+    // 23:30 UTC on the 1st is already the 2nd in Berlin
+    let pending = vec![
+        ("ISSUE-1".to_string(), tagged_log(2, "20210101T223000Z", Some("20210101T224500Z"), &[]), None),
+        ("ISSUE-1".to_string(), tagged_log(1, "20210101T233000Z", Some("20210101T234500Z"), &[]), None),
+    ];
+    let tz = Timezone::from_str("Europe/Berlin").unwrap();
+    assert_eq!(group_pending_logs(pending, Aggregation::Daily, &tz, &Ledger::default()).len(), 2);
+}
+
+#[test]
+fn group_edited_intervals_by_worklog() {
+    let mut ledger = Ledger::default();
+    ledger.record(ledger_entry("20210101T090000Z", "20210101T091000Z", "ISSUE-1", "10001"));
+    ledger.record(ledger_entry("20210101T093000Z", "20210101T100000Z", "ISSUE-1", "10001"));
+    let pending = vec![(
+        "ISSUE-1".to_string(),
+        tagged_log(2, "20210101T093000Z", Some("20210101T103000Z"), &[]),
+        Some(ledger_entry("20210101T093000Z", "20210101T100000Z", "ISSUE-1", "10001")),
+    )];
+    let worklogs = group_pending_logs(pending, Aggregation::Daily, &Timezone::default(), &ledger);
+    assert_eq!(worklogs.len(), 1);
+    assert_eq!(worklogs[0].worklog_id(), Some("10001"));
+    assert_eq!(
+        worklogs[0].unchanged,
+        vec![ledger_entry("20210101T090000Z", "20210101T091000Z", "ISSUE-1", "10001")]
+    );
+    let start = "20210101T090000Z".to_string();
+    let end = "20210101T103000Z".to_string();
+    assert_eq!(worklogs[0].intervals().first().unwrap().0, &start);
+    assert_eq!(worklogs[0].intervals().last().unwrap().1, &end);
+}
+
+// Syncing against a fake Jira client and tagger
+struct FakeJira {
+    jc: JiraConnection,
+    existing: Vec<JiraWorklog>,
+    reject: bool,
+    /// Issues we may not log work on
+    forbidden: Vec<&'static str>,
+    /// The issues whose worklogs were fetched, and since when
+    fetches: Mutex<Vec<(String, Option<time::OffsetDateTime>)>>,
+    uploads: Mutex<Vec<(String, JiraWorklog)>>,
+    /// The worklogs updated, by issue and worklog ID
+    updates: Mutex<Vec<(String, String, JiraWorklog)>>,
+    /// The worklogs deleted, by issue and worklog ID
+    deletes: Mutex<Vec<(String, String)>>,
+    /// The uploads currently in progress, and the most seen at once
+    in_flight: AtomicUsize,
+    max_in_flight: AtomicUsize,
+}
+
+impl FakeJira {
+    fn new(existing: Vec<JiraWorklog>) -> FakeJira {
+        let config = config(&[]);
+        FakeJira {
+            jc: config.jira_connection(),
+            existing,
+            reject: false,
+            forbidden: vec![],
+            fetches: Mutex::new(vec![]),
+            uploads: Mutex::new(vec![]),
+            updates: Mutex::new(vec![]),
+            deletes: Mutex::new(vec![]),
+            in_flight: AtomicUsize::new(0),
+            max_in_flight: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl JiraClient for FakeJira {
+    fn connection(&self) -> &JiraConnection {
+        &self.jc
+    }
+
+    async fn get_worklogs(
+        &self,
+        issue: &str,
+        started_after: Option<time::OffsetDateTime>,
+    ) -> Result<Vec<JiraWorklog>, JiraError> {
+        self.fetches.lock().unwrap().push((issue.to_string(), started_after));
+        match self.reject {
+            true => Err(JiraError::Authentication(reqwest::StatusCode::UNAUTHORIZED)),
+            false => Ok(self.existing.clone()),
+        }
+    }
+
+    async fn upload_worklog(&self, issue: &str, wl: &JiraWorklog) -> Result<String, JiraError> {
+        // Stay in flight for a while, so other uploads get a chance to overlap
+        let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+        self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+        for _ in 0..5 {
+            tokio::task::yield_now().await;
+        }
+        self.in_flight.fetch_sub(1, Ordering::SeqCst);
+        let mut uploads = self.uploads.lock().unwrap();
+        uploads.push((issue.to_string(), wl.clone()));
+        match (self.reject, self.forbidden.contains(&issue)) {
+            (true, _) => Err(JiraError::Authentication(reqwest::StatusCode::UNAUTHORIZED)),
+            (false, true) => Err(JiraError::Forbidden),
+            (false, false) => Ok(format!("{}", 10000 + uploads.len())),
+        }
+    }
+
+    async fn update_worklog(&self, issue: &str, id: &str, wl: &JiraWorklog) -> Result<(), JiraError> {
+        self.updates
+            .lock()
+            .unwrap()
+            .push((issue.to_string(), id.to_string(), wl.clone()));
+        Ok(())
+    }
+
+    async fn delete_worklog(&self, issue: &str, id: &str) -> Result<(), JiraError> {
+        self.deletes
+            .lock()
+            .unwrap()
+            .push((issue.to_string(), id.to_string()));
+        Ok(())
+    }
+}
+
+fn existing_worklog(started: &str, author: &str) -> JiraWorklog {
+    serde_json::from_value(serde_json::json!({
+        "id": "20001",
+        "started": started,
+        "timeSpentSeconds": 3600,
+        "author": { "name": author },
+    }))
+    .unwrap()
+}
+
+#[tokio::test]
+async fn sync_uploads_and_tags() {
+    let client = FakeJira::new(vec![]);
+    let tagger = MemoryTagger::default();
+    let (path, ledger) = temp_ledger("upload");
+    let tw_logs = vec![
+        tagged_log(3, "20210101T090000Z", Some("20210101T093000Z"), &["ISSUE-1"]),
+        tagged_log(2, "20210101T100000Z", Some("20210101T110000Z"), &["meeting"]),
+        tagged_log(1, "20210101T120000Z", None, &["ISSUE-1"]),
+    ];
+    let entries = run(&client, &tagger, &config(&[]), tw_logs, &ledger).await.report;
+
+    let uploads = client.uploads.lock().unwrap();
+    assert_eq!(uploads.len(), 1);
+    assert_eq!(uploads[0].0, "ISSUE-1");
+    assert_eq!(uploads[0].1.started, "2021-01-01T09:00:00.000+0000");
+    assert_eq!(uploads[0].1.time_spent_seconds, 1800);
+    assert_eq!(*tagger.tagged.lock().unwrap(), vec![(3, "jira-uploaded".to_string())]);
+    // The upload is recorded and persisted
+    let saved = Ledger::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(
+        saved.find("20210101T090000Z", "20210101T093000Z", "ISSUE-1").map(|e| e.worklog_id.as_str()),
+        Some("10001")
+    );
+    // Skipped intervals are reported
+    let decisions: Vec<&str> = entries.iter().map(|e| e.decision.as_str()).collect();
+    assert_eq!(
+        decisions,
+        vec!["skipped (no Jira issue tag)", "skipped (interval still running)"]
+    );
+}
+
+#[tokio::test]
+async fn sync_respects_report_range_and_tags() {
+    let client = FakeJira::new(vec![]);
+    let tagger = MemoryTagger::default();
+    let (path, ledger) = temp_ledger("report");
+    let config = config(&[
+        ("temp.report.start", "20210102T000000Z"),
+        ("temp.report.end", "20210103T000000Z"),
+        ("temp.report.tags", "billable"),
+    ]);
+    let tw_logs = vec![
+        // Started before the range, though it ends within it
+        tagged_log(4, "20210101T230000Z", Some("20210102T010000Z"), &["ISSUE-1", "billable"]),
+        tagged_log(3, "20210102T090000Z", Some("20210102T100000Z"), &["ISSUE-1", "billable"]),
+        tagged_log(2, "20210102T110000Z", Some("20210102T120000Z"), &["ISSUE-1"]),
+        tagged_log(1, "20210103T090000Z", Some("20210103T100000Z"), &["ISSUE-1", "billable"]),
+    ];
+    let entries = run(&client, &tagger, &config, tw_logs, &ledger).await.report;
+    std::fs::remove_file(&path).unwrap();
+
+    // Only the interval in the range with the tag is considered, and nothing else is reported
+    assert!(entries.is_empty());
+    let uploads = client.uploads.lock().unwrap();
+    assert_eq!(uploads.len(), 1);
+    assert_eq!(uploads[0].1.started, "2021-01-02T09:00:00.000+0000");
+    // Duplicates are only looked for within the range
+    let fetches = client.fetches.lock().unwrap();
+    assert!(fetches[0].1.unwrap() >= config.range.0.unwrap());
+}
+
+#[tokio::test]
+async fn sync_updates_edited_intervals() {
+    let client = FakeJira::new(vec![]);
+    let tagger = MemoryTagger::default();
+    let (path, ledger) = temp_ledger("update");
+    let old = ledger_entry("20210101T090000Z", "20210101T093000Z", "ISSUE-1", "20001");
+    ledger.lock().unwrap().record(old.clone());
+    // Lengthened with `timew lengthen` since it was uploaded
+    let tw_logs = vec![tagged_log(
+        1,
+        "20210101T090000Z",
+        Some("20210101T100000Z"),
+        &["ISSUE-1", "jira-uploaded"],
+    )];
+    run(&client, &tagger, &config(&[]), tw_logs, &ledger).await;
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(client.uploads.lock().unwrap().is_empty());
+    let updates = client.updates.lock().unwrap();
+    assert_eq!(updates.len(), 1);
+    assert_eq!((updates[0].0.as_str(), updates[0].1.as_str()), ("ISSUE-1", "20001"));
+    assert_eq!(updates[0].2.time_spent_seconds, 3600);
+    // The ledger now records the edited times in place of the old ones
+    let ledger = ledger.lock().unwrap();
+    assert_eq!(
+        ledger.find("20210101T090000Z", "20210101T100000Z", "ISSUE-1").map(|e| e.worklog_id.as_str()),
+        Some("20001")
+    );
+    assert_eq!(ledger.find(&old.start, &old.end, &old.issue), None);
+    assert_eq!(ledger.find_worklog("20001").len(), 1);
+}
+
+#[tokio::test]
+async fn sync_reports_moved_intervals() {
+    let client = FakeJira::new(vec![]);
+    let tagger = MemoryTagger::default();
+    let (path, ledger) = temp_ledger("moved");
+    ledger
+        .lock()
+        .unwrap()
+        .record(ledger_entry("20210101T090000Z", "20210101T093000Z", "ISSUE-1", "20001"));
+    // Moved with `timew move`, which changes both ends
+    let tw_logs = vec![tagged_log(
+        1,
+        "20210101T100000Z",
+        Some("20210101T103000Z"),
+        &["ISSUE-1", "jira-uploaded"],
+    )];
+    let entries = run(&client, &tagger, &config(&[]), tw_logs, &ledger).await.report;
+    let _ = std::fs::remove_file(&path);
+
+    assert!(client.updates.lock().unwrap().is_empty());
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].decision, "skipped (tagged jira-uploaded, but not in upload ledger)");
+}
+
+#[tokio::test]
+async fn sync_skips_existing_worklogs() {
+    let client = FakeJira::new(vec![
+        existing_worklog("2021-01-01T09:00:00.000+0000", "user@myjira.com"),
+        existing_worklog("2021-01-01T10:00:00.000+0000", "someone-else"),
+    ]);
+    let tagger = MemoryTagger::default();
+    let (path, ledger) = temp_ledger("existing");
+    let tw_logs = vec![
+        tagged_log(2, "20210101T090000Z", Some("20210101T093000Z"), &["ISSUE-1"]),
+        tagged_log(1, "20210101T100000Z", Some("20210101T110000Z"), &["ISSUE-1"]),
+    ];
+    run(&client, &tagger, &config(&[]), tw_logs, &ledger).await;
+    std::fs::remove_file(&path).unwrap();
+
+    // Only someone else's worklog exists at 10:00, so ours is still uploaded
+    let uploads = client.uploads.lock().unwrap();
+    assert_eq!(uploads.len(), 1);
+    assert_eq!(uploads[0].1.started, "2021-01-01T10:00:00.000+0000");
+    // Existing worklogs are fetched once per issue
+    let fetches = client.fetches.lock().unwrap();
+    assert_eq!(fetches.iter().map(|(issue, _)| issue.as_str()).collect::<Vec<_>>(), vec!["ISSUE-1"]);
+    // Both intervals are marked as uploaded, and the existing worklog is recorded
+    let mut tagged = tagger.tagged.lock().unwrap().clone();
+    tagged.sort();
+    assert_eq!(tagged, vec![(1, "jira-uploaded".to_string()), (2, "jira-uploaded".to_string())]);
+    assert_eq!(
+        ledger
+            .lock()
+            .unwrap()
+            .find("20210101T090000Z", "20210101T093000Z", "ISSUE-1")
+            .map(|e| e.worklog_id.clone()),
+        Some("20001".to_string())
+    );
+}
+
+#[tokio::test]
+async fn sync_fetches_worklogs_once_per_issue() {
+    let client = FakeJira::new(vec![]);
+    let tagger = MemoryTagger::default();
+    let (path, ledger) = temp_ledger("fetch-once");
+    let tw_logs = vec![
+        tagged_log(4, "20210101T090000Z", Some("20210101T093000Z"), &["ISSUE-1"]),
+        tagged_log(3, "20210101T100000Z", Some("20210101T110000Z"), &["ISSUE-2"]),
+        tagged_log(2, "20210101T120000Z", Some("20210101T130000Z"), &["ISSUE-1"]),
+        tagged_log(1, "20210101T140000Z", Some("20210101T150000Z"), &["ISSUE-1"]),
+    ];
+    run(&client, &tagger, &config(&[]), tw_logs, &ledger).await;
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(client.uploads.lock().unwrap().len(), 4);
+    let mut fetched: Vec<String> = client.fetches.lock().unwrap().iter().map(|(i, _)| i.clone()).collect();
+    fetched.sort();
+    assert_eq!(fetched, vec!["ISSUE-1", "ISSUE-2"]);
+}
+
+#[tokio::test]
+async fn sync_fetches_worklogs_since_earliest_interval() {
+    let client = FakeJira::new(vec![]);
+    let tagger = MemoryTagger::default();
+    let (path, ledger) = temp_ledger("started-after");
+    let tw_logs = vec![
+        tagged_log(3, "20210101T090000Z", Some("20210101T093000Z"), &["ISSUE-1"]),
+        tagged_log(2, "20210102T100000Z", Some("20210102T110000Z"), &["ISSUE-1"]),
+        tagged_log(1, "20210103T120000Z", Some("20210103T130000Z"), &["ISSUE-2"]),
+    ];
+    run(&client, &tagger, &config(&[]), tw_logs, &ledger).await;
+    std::fs::remove_file(&path).unwrap();
+
+    // Each issue's worklogs are fetched from just before its earliest interval
+    let mut fetches: Vec<(String, i64)> = client
+        .fetches
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(issue, started_after)| (issue.clone(), started_after.unwrap().unix_timestamp()))
+        .collect();
+    fetches.sort();
+    assert_eq!(
+        fetches,
+        vec![("ISSUE-1".to_string(), 1609491599), ("ISSUE-2".to_string(), 1609675199)]
+    );
+}
+
+#[tokio::test]
+async fn sync_dry_run() {
+    let client = FakeJira::new(vec![existing_worklog(
+        "2021-01-01T09:00:00.000+0000",
+        "user@myjira.com",
+    )]);
+    let tagger = MemoryTagger::default();
+    let (_, ledger) = temp_ledger("dry-run");
+    let tw_logs = vec![
+        tagged_log(2, "20210101T090000Z", Some("20210101T093000Z"), &["ISSUE-1"]),
+        tagged_log(1, "20210101T100000Z", Some("20210101T110000Z"), &["ISSUE-1"]),
+    ];
+    let config = config(&[("twjp.dry_run", "on")]);
+    let mut entries = run(&client, &tagger, &config, tw_logs, &ledger).await.report;
+    entries.sort_by(|a, b| a.start.cmp(&b.start));
+
+    let decisions: Vec<&str> = entries.iter().map(|e| e.decision.as_str()).collect();
+    assert_eq!(decisions, vec!["already exists", "would upload"]);
+    assert!(client.uploads.lock().unwrap().is_empty());
+    assert!(tagger.tagged.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn sync_reports_rounding() {
+    let client = FakeJira::new(vec![]);
+    let tagger = MemoryTagger::default();
+    let (path, ledger) = temp_ledger("rounding");
+    let tw_logs = vec![tagged_log(1, "20210101T090000Z", Some("20210101T090700Z"), &["ISSUE-1"])];
+    let config = config(&[("twjp.rounding.mode", "up"), ("twjp.rounding.increment", "15")]);
+    let outcome = run(&client, &tagger, &config, tw_logs, &ledger).await;
+    let _ = std::fs::remove_file(&path);
+
+    assert_eq!(client.uploads.lock().unwrap()[0].1.time_spent_seconds, 900);
+    assert_eq!(
+        outcome.adjustments,
+        vec!["Rounded ISSUE-1 worklog at 2021-01-01 09:00:00 +0000 from 0:07:00 to 0:15:00"]
+    );
+}
+
+#[tokio::test]
+async fn sync_reports_no_rounding_for_existing_worklog() {
+    let client = FakeJira::new(vec![existing_worklog("2021-01-01T09:00:00.000+0000", "user@myjira.com")]);
+    let tagger = MemoryTagger::default();
+    let (path, ledger) = temp_ledger("rounding-existing");
+    let tw_logs = vec![tagged_log(1, "20210101T090000Z", Some("20210101T090700Z"), &["ISSUE-1"])];
+    let config = config(&[("twjp.rounding.mode", "up"), ("twjp.rounding.increment", "15")]);
+    let outcome = run(&client, &tagger, &config, tw_logs, &ledger).await;
+    let _ = std::fs::remove_file(&path);
+
+    assert!(client.uploads.lock().unwrap().is_empty());
+    assert!(outcome.adjustments.is_empty());
+}
+
+#[tokio::test]
+async fn sync_limits_concurrency() {
+    let client = FakeJira::new(vec![]);
+    let tagger = MemoryTagger::default();
+    let (path, ledger) = temp_ledger("concurrency");
+    let tw_logs = (0..10)
+        .map(|i| {
+            let start = format!("202101{:02}T090000Z", i + 1);
+            let end = format!("202101{:02}T100000Z", i + 1);
+            tagged_log(10 - i, &start, Some(&end), &["ISSUE-1"])
+        })
+        .collect();
+    let config = config(&[("twjp.max_concurrency", "3")]);
+    run(&client, &tagger, &config, tw_logs, &ledger).await;
+    let _ = std::fs::remove_file(&path);
+
+    assert_eq!(client.uploads.lock().unwrap().len(), 10);
+    let max_in_flight = client.max_in_flight.load(Ordering::SeqCst);
+    assert!(max_in_flight > 1 && max_in_flight <= 3, "{} uploads in flight at once", max_in_flight);
+}
+
+#[tokio::test]
+async fn sync_aborts_on_authentication_failure() {
+    let mut client = FakeJira::new(vec![]);
+    client.reject = true;
+    let tagger = MemoryTagger::default();
+    let (_, ledger) = temp_ledger("abort");
+    let tw_logs = vec![
+        tagged_log(2, "20210101T090000Z", Some("20210101T093000Z"), &["ISSUE-1"]),
+        tagged_log(1, "20210101T100000Z", Some("20210101T110000Z"), &["ISSUE-2"]),
+    ];
+    let config = config(&[("twjp.max_concurrency", "1")]);
+    run(&client, &tagger, &config, tw_logs, &ledger).await;
+
+    // Only the first request is sent
+    assert_eq!(client.fetches.lock().unwrap().len(), 1);
+    assert!(client.uploads.lock().unwrap().is_empty());
+    assert!(tagger.tagged.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn sync_dry_run_reports_aborted_worklogs() {
+    let mut client = FakeJira::new(vec![]);
+    client.reject = true;
+    let tagger = MemoryTagger::default();
+    let (_, ledger) = temp_ledger("abort-dry-run");
+    let tw_logs = vec![
+        tagged_log(2, "20210101T090000Z", Some("20210101T093000Z"), &["ISSUE-1"]),
+        tagged_log(1, "20210101T100000Z", Some("20210101T110000Z"), &["ISSUE-2"]),
+    ];
+    let config = config(&[("twjp.max_concurrency", "1"), ("temp.report.tags", "dry-run")]);
+    let entries = run(&client, &tagger, &config, tw_logs, &ledger).await.report;
+
+    let decisions: Vec<&str> = entries.iter().map(|e| e.decision.as_str()).collect();
+    assert_eq!(decisions.len(), 2);
+    assert!(decisions[0].starts_with("skipped (error: Authentication failed"), "{}", decisions[0]);
+    assert_eq!(decisions[1], "skipped (aborted: Jira rejected the credentials)");
+}
+
+#[tokio::test]
+async fn sync_continues_after_forbidden_issue() {
+    let mut client = FakeJira::new(vec![]);
+    client.forbidden = vec!["ISSUE-1"];
+    let tagger = MemoryTagger::default();
+    let (path, ledger) = temp_ledger("forbidden");
+    let tw_logs = vec![
+        tagged_log(2, "20210101T090000Z", Some("20210101T093000Z"), &["ISSUE-1"]),
+        tagged_log(1, "20210101T100000Z", Some("20210101T110000Z"), &["ISSUE-2"]),
+    ];
+    let config = config(&[("twjp.max_concurrency", "1")]);
+    run(&client, &tagger, &config, tw_logs, &ledger).await;
+    std::fs::remove_file(&path).unwrap();
+
+    // The other issue is still uploaded and tagged
+    assert_eq!(client.uploads.lock().unwrap().len(), 2);
+    assert_eq!(*tagger.tagged.lock().unwrap(), vec![(1, "jira-uploaded".to_string())]);
+}
+
+// Reconciliation
+fn worklog_with_id(id: &str, started: &str, author: &str) -> JiraWorklog {
+    let mut wl = existing_worklog(started, author);
+    wl.id = Some(id.to_string());
+    wl
+}
+
+fn decisions(entries: &[ReportEntry]) -> Vec<&str> {
+    entries.iter().map(|e| e.decision.as_str()).collect()
+}
+
+#[tokio::test]
+async fn reconcile_lists_orphans_by_the_user() {
+    let client = FakeJira::new(vec![
+        worklog_with_id("20001", "2021-01-01T09:00:00.000+0000", "user@myjira.com"),
+        worklog_with_id("20002", "2021-01-01T10:00:00.000+0000", "user@myjira.com"),
+        worklog_with_id("20003", "2021-01-01T11:00:00.000+0000", "someone-else"),
+    ]);
+    let tagger = MemoryTagger::default();
+    let (path, ledger) = temp_ledger("reconcile");
+    let tw_logs = vec![tagged_log(1, "20210101T090000Z", Some("20210101T093000Z"), &["ISSUE-1"])];
+    let entries = run(&client, &tagger, &config(&[("twjp.reconcile", "true")]), tw_logs, &ledger).await.report;
+    let _ = std::fs::remove_file(&path);
+
+    // The worklog with a local interval and someone else's worklog aren't orphans
+    assert_eq!(decisions(&entries), vec!["orphaned worklog 20002"]);
+    assert_eq!(entries[0].issue, "ISSUE-1");
+    assert!(client.uploads.lock().unwrap().is_empty());
+    assert!(client.deletes.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn reconcile_respects_range() {
+    let client = FakeJira::new(vec![
+        worklog_with_id("20001", "2021-01-01T10:00:00.000+0000", "user@myjira.com"),
+        worklog_with_id("20002", "2021-01-02T10:00:00.000+0000", "user@myjira.com"),
+        worklog_with_id("20003", "2021-01-03T10:00:00.000+0000", "user@myjira.com"),
+    ]);
+    let tagger = MemoryTagger::default();
+    let (path, ledger) = temp_ledger("reconcile-range");
+    let config = config(&[
+        ("twjp.reconcile", "true"),
+        ("temp.report.start", "20210102T000000Z"),
+        ("temp.report.end", "20210103T000000Z"),
+    ]);
+    let tw_logs = vec![tagged_log(1, "20210102T090000Z", Some("20210102T093000Z"), &["ISSUE-1"])];
+    let entries = run(&client, &tagger, &config, tw_logs, &ledger).await.report;
+    let _ = std::fs::remove_file(&path);
+
+    assert_eq!(decisions(&entries), vec!["orphaned worklog 20002"]);
+    // Worklogs are only fetched from the start of the range
+    let fetches = client.fetches.lock().unwrap();
+    assert!(fetches[0].1.unwrap() < config.range.0.unwrap());
+    assert!(fetches[0].1.unwrap() > config.range.0.unwrap() - time::Duration::minute());
+}
+
+#[tokio::test]
+async fn reconcile_checks_issues_of_deleted_intervals() {
+    let client = FakeJira::new(vec![worklog_with_id(
+        "20001",
+        "2021-01-01T09:00:00.000+0000",
+        "user@myjira.com",
+    )]);
+    let tagger = MemoryTagger::default();
+    let (path, ledger) = temp_ledger("reconcile-deleted");
+    // The only interval on the issue was deleted locally, leaving just its ledger record
+    ledger
+        .lock()
+        .unwrap()
+        .record(ledger_entry("20210101T090000Z", "20210101T100000Z", "ISSUE-9", "20001"));
+    let entries = run(&client, &tagger, &config(&[("twjp.reconcile", "true")]), vec![], &ledger).await.report;
+    let _ = std::fs::remove_file(&path);
+
+    let fetches = client.fetches.lock().unwrap();
+    assert_eq!(fetches.iter().map(|(issue, _)| issue.as_str()).collect::<Vec<_>>(), vec!["ISSUE-9"]);
+    assert_eq!(decisions(&entries), vec!["orphaned worklog 20001"]);
+}
+
+#[tokio::test]
+async fn reconcile_deletes_only_uploaded_orphans() {
+    let existing = vec![
+        worklog_with_id("20001", "2021-01-01T10:00:00.000+0000", "user@myjira.com"),
+        // Logged in Jira by hand
+        worklog_with_id("20002", "2021-01-01T11:00:00.000+0000", "user@myjira.com"),
+    ];
+    let tagger = MemoryTagger::default();
+    let (path, ledger) = temp_ledger("reconcile-delete");
+    ledger
+        .lock()
+        .unwrap()
+        .record(ledger_entry("20210101T100000Z", "20210101T110000Z", "ISSUE-1", "20001"));
+    let tw_logs = vec![tagged_log(1, "20210101T090000Z", Some("20210101T093000Z"), &["ISSUE-1"])];
+
+    // A dry run only lists what would be deleted
+    let client = FakeJira::new(existing.clone());
+    let dry_run = config(&[
+        ("twjp.reconcile", "true"),
+        ("twjp.delete_orphans", "true"),
+        ("twjp.dry_run", "true"),
+    ]);
+    let entries = run(&client, &tagger, &dry_run, tw_logs.clone(), &ledger).await.report;
+    assert_eq!(
+        decisions(&entries),
+        vec![
+            "would delete orphaned worklog 20001",
+            "orphaned worklog 20002 (not in upload ledger, not deleted)"
+        ]
+    );
+    assert!(client.deletes.lock().unwrap().is_empty());
+
+    let client = FakeJira::new(existing);
+    let delete = config(&[("twjp.reconcile", "true"), ("twjp.delete_orphans", "true")]);
+    let entries = run(&client, &tagger, &delete, tw_logs, &ledger).await.report;
+    let saved = Ledger::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(
+        decisions(&entries),
+        vec![
+            "deleted orphaned worklog 20001",
+            "orphaned worklog 20002 (not in upload ledger, not deleted)"
+        ]
+    );
+    assert_eq!(
+        *client.deletes.lock().unwrap(),
+        vec![("ISSUE-1".to_string(), "20001".to_string())]
+    );
+    // The deleted worklog is forgotten, and the change persisted
+    assert!(ledger.lock().unwrap().find_worklog("20001").is_empty());
+    assert!(saved.find_worklog("20001").is_empty());
+}
+
+#[tokio::test]
+async fn reconcile_keeps_aggregated_worklog_with_remaining_intervals() {
+    let client = FakeJira::new(vec![worklog_with_id(
+        "20001",
+        "2021-01-01T09:00:00.000+0000",
+        "user@myjira.com",
+    )]);
+    let tagger = MemoryTagger::default();
+    let (path, ledger) = temp_ledger("reconcile-aggregated");
+    {
+        let mut ledger = ledger.lock().unwrap();
+        ledger.record(ledger_entry("20210101T090000Z", "20210101T093000Z", "ISSUE-1", "20001"));
+        ledger.record(ledger_entry("20210101T110000Z", "20210101T120000Z", "ISSUE-1", "20001"));
+    }
+    // The earliest interval of the day was deleted, the other one remains
+    let tw_logs = vec![tagged_log(
+        1,
+        "20210101T110000Z",
+        Some("20210101T120000Z"),
+        &["ISSUE-1", "jira-uploaded"],
+    )];
+    let config = config(&[
+        ("twjp.aggregate", "daily"),
+        ("twjp.reconcile", "true"),
+        ("twjp.delete_orphans", "true"),
+    ]);
+    let entries = run(&client, &tagger, &config, tw_logs, &ledger).await.report;
+    let _ = std::fs::remove_file(&path);
+
+    assert!(entries.is_empty());
+    assert!(client.deletes.lock().unwrap().is_empty());
+    assert_eq!(ledger.lock().unwrap().find_worklog("20001").len(), 2);
+}
+
+#[tokio::test]
+async fn reconcile_keeps_worklog_of_edited_interval() {
+    let client = FakeJira::new(vec![worklog_with_id(
+        "20001",
+        "2021-01-01T09:00:00.000+0000",
+        "user@myjira.com",
+    )]);
+    let tagger = MemoryTagger::default();
+    let (path, ledger) = temp_ledger("reconcile-edited");
+    ledger
+        .lock()
+        .unwrap()
+        .record(ledger_entry("20210101T090000Z", "20210101T100000Z", "ISSUE-1", "20001"));
+    // Edited with `timew modify start`, but not synced yet
+    let tw_logs = vec![tagged_log(
+        1,
+        "20210101T091500Z",
+        Some("20210101T100000Z"),
+        &["ISSUE-1", "jira-uploaded"],
+    )];
+    let config = config(&[("twjp.reconcile", "true"), ("twjp.delete_orphans", "true")]);
+    let entries = run(&client, &tagger, &config, tw_logs, &ledger).await.report;
+    let _ = std::fs::remove_file(&path);
+
+    assert!(entries.is_empty());
+    assert!(client.deletes.lock().unwrap().is_empty());
+}
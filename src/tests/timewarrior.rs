@@ -51,6 +51,32 @@ fn validate_config_keys(input: &str) {
     assert_eq!(twc["verbose"], "on");
 }
 
+#[test]
+fn validate_report_tags() {
+    let mut twc = std::collections::HashMap::new();
+    assert!(report_tags(&twc).is_empty());
+    twc.insert(
+        "temp.report.tags".to_string(),
+        "check,\"multi word tag\"".to_string(),
+    );
+    assert_eq!(report_tags(&twc), vec!["check", "multi word tag"]);
+}
+
+#[test]
+fn validate_log_parsing_annotations() {
+    const EXAMPLE_ANNOTATED_STDIN: &str = r#"temp.version: 1.4.2
+twjp.url: https://myjira.atlassian.net
+
+[
+{"id":2,"start":"20210101T000000Z","end":"20210102T000000Z","tags":["ISSUE-2"],"annotation":"Reviewed the pull request"},
+{"id":1,"start":"20210102T000000Z","tags":["ISSUE-1"]}
+]
+"#;
+    let (_, twl) = parse_tw_input(EXAMPLE_ANNOTATED_STDIN).unwrap();
+    assert_eq!(twl[0].annotation, Some("Reviewed the pull request".to_string()));
+    assert_eq!(twl[1].annotation, None);
+}
+
 pub(crate) mod tw130 {
     const EXAMPLE_TW13_STDIN: &str = {
         r#"temp.version: 1.4.2
@@ -146,3 +172,120 @@ verbose: on
         super::validate_config_keys(&EXAMPLE_TW12_STDIN);
     }
 }
+
+/// Writes a stand-in for timew which records its arguments, then runs the given shell commands
+#[cfg(unix)]
+fn fake_timew(name: &str, script: &str) -> (std::path::PathBuf, std::path::PathBuf) {
+    use std::os::unix::fs::PermissionsExt;
+    let dir = std::env::temp_dir();
+    let program = dir.join(format!("twjp-timew-{}-{}", name, std::process::id()));
+    let args = dir.join(format!("twjp-timew-{}-{}.args", name, std::process::id()));
+    std::fs::write(
+        &program,
+        format!("#!/bin/sh\necho \"$@\" >> {}\n{}\n", args.display(), script),
+    )
+    .unwrap();
+    std::fs::set_permissions(&program, std::fs::Permissions::from_mode(0o755)).unwrap();
+    (program, args)
+}
+
+fn interval(id: usize, hour: usize) -> TimeWarriorLog {
+    TimeWarriorLog {
+        id,
+        start: format!("20210101T{:02}0000Z", hour),
+        end: Some(format!("20210101T{:02}0000Z", hour + 1)),
+        tags: vec![],
+        annotation: None,
+    }
+}
+
+/// A script answering `timew export` with the given intervals, and running `tag` for any other command
+fn export_then(intervals: &[TimeWarriorLog], tag: &str) -> String {
+    format!(
+        "if [ \"$1\" = export ]; then echo '{}'; exit 0; fi\n{}",
+        serde_json::to_string(intervals).unwrap(),
+        tag
+    )
+}
+
+#[cfg(unix)]
+fn run_tagger(name: &str, script: &str, tw_logs: &[&TimeWarriorLog]) -> (Result<(), String>, String) {
+    let (program, args) = fake_timew(name, script);
+    let tagger = TimewTagger {
+        program: program.display().to_string(),
+    };
+    let result = tagger.tag(tw_logs, "jira-uploaded");
+    let recorded = std::fs::read_to_string(&args).unwrap();
+    std::fs::remove_file(&program).unwrap();
+    std::fs::remove_file(&args).unwrap();
+    (result, recorded)
+}
+
+#[cfg(unix)]
+#[test]
+fn tag_in_single_call() {
+    let logs = [interval(3, 0), interval(1, 2)];
+    let (result, recorded) = run_tagger("batch", &export_then(&logs, "exit 0"), &[&logs[0], &logs[1]]);
+    assert_eq!(result, Ok(()));
+    assert_eq!(
+        recorded,
+        "export 20210101T000000Z - 20210101T030000Z\ntag @3 @1 jira-uploaded\n"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn tag_reports_failures() {
+    let logs = [interval(3, 0)];
+    let script = export_then(&logs, "echo 'There is no interval @3.' >&2\nexit 255");
+    let (result, _) = run_tagger("failure", &script, &[&logs[0]]);
+    assert_eq!(
+        result,
+        Err("Error tagging @3 (exit status: 255): There is no interval @3.".to_string())
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn tag_follows_shifted_ids() {
+    // A new interval was tracked since the intervals were read, shifting their IDs
+    let logs = [interval(2, 0), interval(1, 2)];
+    let current = [interval(3, 0), interval(2, 2), interval(1, 4)];
+    let (result, recorded) = run_tagger("shifted", &export_then(&current, "exit 0"), &[&logs[0], &logs[1]]);
+    assert_eq!(result, Ok(()));
+    assert!(recorded.ends_with("tag @3 @2 jira-uploaded\n"), "{}", recorded);
+}
+
+#[cfg(unix)]
+#[test]
+fn tag_refuses_changed_intervals() {
+    // The second interval was edited since it was read
+    let logs = [interval(2, 0), interval(1, 2)];
+    let mut edited = interval(1, 2);
+    edited.end = Some("20210101T023000Z".to_string());
+    let current = [interval(2, 0), edited];
+    let (result, recorded) = run_tagger("changed", &export_then(&current, "exit 0"), &[&logs[0], &logs[1]]);
+    assert_eq!(
+        result,
+        Err("Intervals @1 changed since they were read, so they were not tagged".to_string())
+    );
+    assert!(recorded.ends_with("tag @2 jira-uploaded\n"), "{}", recorded);
+}
+
+#[test]
+fn current_ids_without_export_ids() {
+    // TimeWarrior before 1.3 doesn't export IDs, so they can't be verified
+    let exported: Vec<ExportedInterval> =
+        serde_json::from_str(r#"[{"start":"20210101T000000Z","end":"20210101T010000Z"}]"#).unwrap();
+    assert_eq!(current_ids(&[&interval(1, 0)], &exported), (vec![], vec!["@1".to_string()]));
+}
+
+#[test]
+fn memory_tagger_records_tags() {
+    let tagger = MemoryTagger::default();
+    tagger.tag(&[&interval(2, 0), &interval(1, 2)], "jira-uploaded").unwrap();
+    assert_eq!(
+        *tagger.tagged.lock().unwrap(),
+        vec![(2, "jira-uploaded".to_string()), (1, "jira-uploaded".to_string())]
+    );
+}
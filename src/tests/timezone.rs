@@ -0,0 +1,57 @@
+use crate::jira::WORKLOG_TIME_FORMAT;
+use crate::timewarrior::parse_tw_time;
+use crate::timezone::*;
+use std::str::FromStr;
+
+// Utility functions
+fn started(tw_time: &str, tz: &str) -> String {
+    parse_tw_time(tw_time, &Timezone::from_str(tz).unwrap())
+        .unwrap()
+        .format(WORKLOG_TIME_FORMAT)
+}
+
+#[test]
+fn timezone_default_utc() {
+    assert_eq!(
+        parse_tw_time("20210101T120000Z", &Timezone::default())
+            .unwrap()
+            .format(WORKLOG_TIME_FORMAT),
+        "2021-01-01T12:00:00.000+0000"
+    );
+}
+
+#[test]
+fn timezone_fixed_offset() {
+    assert_eq!(started("20210101T120000Z", "+0130"), "2021-01-01T13:30:00.000+0130");
+    assert_eq!(started("20210101T120000Z", "-0500"), "2021-01-01T07:00:00.000-0500");
+}
+
+#[test]
+fn timezone_named() {
+    assert_eq!(started("20210101T120000Z", "Europe/Berlin"), "2021-01-01T13:00:00.000+0100");
+    assert_eq!(started("20210701T120000Z", "Europe/Berlin"), "2021-07-01T14:00:00.000+0200");
+}
+
+#[test]
+fn timezone_named_dst_transition() {
+    // Clocks in Berlin went forward at 01:00 UTC on 2021-03-28
+    assert_eq!(started("20210328T005959Z", "Europe/Berlin"), "2021-03-28T01:59:59.000+0100");
+    assert_eq!(started("20210328T010000Z", "Europe/Berlin"), "2021-03-28T03:00:00.000+0200");
+    // and back at 01:00 UTC on 2021-10-31
+    assert_eq!(started("20211031T005959Z", "Europe/Berlin"), "2021-10-31T02:59:59.000+0200");
+    assert_eq!(started("20211031T010000Z", "Europe/Berlin"), "2021-10-31T02:00:00.000+0100");
+}
+
+#[test]
+fn timezone_preserves_instant() {
+    let tz = Timezone::from_str("America/New_York").unwrap();
+    assert_eq!(
+        parse_tw_time("20210101T120000Z", &tz).unwrap(),
+        parse_tw_time("20210101T120000Z", &Timezone::default()).unwrap()
+    );
+}
+
+#[test]
+fn timezone_invalid() {
+    assert!(Timezone::from_str("Mars/Olympus_Mons").is_err());
+}
@@ -0,0 +1,46 @@
+//! Helpers shared by the tests of several modules
+use crate::config::Config;
+use crate::ledger::Ledger;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// A TimeWarrior config block with the required Jira settings, and the given keys added or replaced
+pub fn tw_config(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+    let mut tw_conf: HashMap<String, String> = [
+        ("twjp.url", "https://myjira.atlassian.net"),
+        ("twjp.user", "user@myjira.com"),
+        ("twjp.token", "secret"),
+    ]
+    .iter()
+    .map(|(k, v)| (k.to_string(), v.to_string()))
+    .collect();
+    for (k, v) in pairs {
+        tw_conf.insert(k.to_string(), v.to_string());
+    }
+    tw_conf
+}
+
+/// Validates a config block without looking at the process environment, so `TWJP_TOKEN` can't affect the tests
+pub fn from_tw_config(tw_conf: &HashMap<String, String>) -> Result<Config, Vec<String>> {
+    Config::from_tw_config_with_env(tw_conf, &HashMap::new())
+}
+
+/// The validated config of `tw_config`
+pub fn config(pairs: &[(&str, &str)]) -> Config {
+    from_tw_config(&tw_config(pairs)).unwrap()
+}
+
+/// A path in the temporary directory unique to this test run, with any leftover file removed
+pub fn temp_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("twjp-{}-{}", std::process::id(), name));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+/// An empty ledger, stored at a temporary path
+pub fn temp_ledger(name: &str) -> (PathBuf, Mutex<Ledger>) {
+    let path = temp_path(&format!("ledger-{}.json", name));
+    let ledger = Ledger::load(&path).unwrap();
+    (path, Mutex::new(ledger))
+}
@@ -1,27 +1,32 @@
+use crate::timezone::Timezone;
 use log::{debug, warn};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use time::{OffsetDateTime, PrimitiveDateTime};
 
 /// A structure representing a single TimeWarrior log entry.
 /// # Examples
 /// These logs come from TimeWarrior as a JSON object:
-/// ```
+/// ```json
 /// {
 ///   "start":"20160405T162205Z",
 ///   "end":"20160405T162211Z",
-///   "tags":["This is a multi-word tag","ProjectA","tag123"]
+///   "tags":["This is a multi-word tag","ProjectA","tag123"],
+///   "annotation":"Reviewed the pull request"
 /// }
 /// ```
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TimeWarriorLog {
     pub id: usize,
     pub start: String,
     pub end: Option<String>,
     pub tags: Vec<String>,
+    pub annotation: Option<String>,
 }
 
 /// Takes given TimeWarrior input and parses config and logs from it.
 /// Returns a HashMap with configuration and a list of logs as a tuple.
+#[allow(clippy::redundant_field_names)]
 pub fn parse_tw_input(
     input: &str,
 ) -> Result<(HashMap<String, String>, Vec<TimeWarriorLog>), String> {
@@ -48,6 +53,7 @@ pub fn parse_tw_input(
         pub start: String,
         pub end: Option<String>,
         pub tags: Vec<String>,
+        pub annotation: Option<String>,
     }
     debug!("Parsing entries: {:?}", entries);
     let mut tw_logs: Vec<TimeWarriorLogRaw> = match serde_json::from_str(&entries) {
@@ -69,6 +75,7 @@ pub fn parse_tw_input(
                     start: l.start,
                     end: l.end,
                     tags: l.tags,
+                    annotation: l.annotation,
                 }),
                 None => {
                     // For old TimeWarrior versions, we need to infer the ID
@@ -85,6 +92,7 @@ pub fn parse_tw_input(
                                 start: l.start,
                                 end: l.end,
                                 tags: l.tags,
+                                annotation: l.annotation,
                             })
                         },
                         false => None,
@@ -127,17 +135,152 @@ fn parse_tw_config(block: &str) -> HashMap<String, String> {
     tw_conf
 }
 
-/// Tag a timewarrior interval
-pub fn tag_tw_log(tw_log: &TimeWarriorLog, tag: &str) -> Result<(), String> {
-    // Call the interval as uploaded
-    match std::process::Command::new("timew")
-        .args(&["tag", &format!("@{}", tw_log.id), tag])
+/// The tags given on the command line, e.g. `check` for `timew jirapush check`.
+/// TimeWarrior passes these comma-separated, quoting tags which contain spaces.
+pub fn report_tags(tw_conf: &HashMap<String, String>) -> Vec<String> {
+    match tw_conf.get("temp.report.tags") {
+        Some(tags) => tags
+            .split(',')
+            .map(|t| t.trim().trim_matches('"').to_string())
+            .filter(|t| !t.is_empty())
+            .collect(),
+        None => vec![],
+    }
+}
+
+/// Parses a sparse ISO8601 timestamp handed off by TimeWarrior, such as `20210101T000000Z`.
+/// These are always UTC; the result is converted to local time in the given timezone.
+pub fn parse_tw_time(tw_time: &str, tz: &Timezone) -> Result<OffsetDateTime, time::ParseError> {
+    Ok(tz.to_local(PrimitiveDateTime::parse(tw_time, "%Y%m%dT%H%M%SZ")?.assume_utc()))
+}
+
+/// Marks intervals in TimeWarrior, e.g. as uploaded
+pub trait Tagger {
+    /// Adds a tag to all of the given intervals at once
+    fn tag(&self, tw_logs: &[&TimeWarriorLog], tag: &str) -> Result<(), String>;
+}
+
+/// Tags intervals by running `timew`
+#[derive(Debug)]
+pub struct TimewTagger {
+    /// The timew executable
+    pub program: String,
+}
+
+impl Default for TimewTagger {
+    fn default() -> Self {
+        TimewTagger {
+            program: "timew".to_string(),
+        }
+    }
+}
+
+impl Tagger for TimewTagger {
+    fn tag(&self, tw_logs: &[&TimeWarriorLog], tag: &str) -> Result<(), String> {
+        if tw_logs.is_empty() {
+            return Ok(());
+        }
+        // IDs may have shifted since we were called, so look up the intervals' current IDs
+        let exported = export_tw_logs(&self.program, tw_logs)?;
+        let (ids, missing) = current_ids(tw_logs, &exported);
+        tag_tw_logs(&self.program, &ids, tag)?;
+        match missing.is_empty() {
+            true => Ok(()),
+            false => Err(format!(
+                "Intervals {} changed since they were read, so they were not tagged",
+                missing.join(", ")
+            )),
+        }
+    }
+}
+
+/// Tags intervals in memory instead of in TimeWarrior, e.g. for tests or dry runs
+#[derive(Debug, Default)]
+pub struct MemoryTagger {
+    /// The ID of each tagged interval, with its tag
+    pub tagged: std::sync::Mutex<Vec<(usize, String)>>,
+}
+
+impl Tagger for MemoryTagger {
+    fn tag(&self, tw_logs: &[&TimeWarriorLog], tag: &str) -> Result<(), String> {
+        let mut tagged = self.tagged.lock().unwrap();
+        tagged.extend(tw_logs.iter().map(|l| (l.id, tag.to_string())));
+        Ok(())
+    }
+}
+
+/// An interval as currently exported by `timew export`
+#[derive(Deserialize, Debug)]
+pub struct ExportedInterval {
+    pub id: Option<usize>,
+    pub start: String,
+    pub end: Option<String>,
+}
+
+/// Runs `timew export` for the time span covering the given intervals
+fn export_tw_logs(program: &str, tw_logs: &[&TimeWarriorLog]) -> Result<Vec<ExportedInterval>, String> {
+    // TimeWarrior timestamps sort chronologically as strings
+    let first = tw_logs.iter().map(|l| &l.start).min().unwrap();
+    let last = tw_logs.iter().filter_map(|l| l.end.as_ref()).max().unwrap_or(first);
+    let output = std::process::Command::new(program)
+        .args(["export", first, "-", last])
+        .output()
+        .map_err(|e| format!("Error running timew export: {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "Error running timew export ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    serde_json::from_slice(&output.stdout).map_err(|e| format!("Error parsing timew export: {}", e))
+}
+
+/// Finds the current IDs of intervals, which are positional and shift when intervals are added or removed.
+/// Intervals are identified by their start and end times. Returns the IDs found,
+/// and the original IDs of intervals which no longer exist as they were read.
+pub fn current_ids(tw_logs: &[&TimeWarriorLog], exported: &[ExportedInterval]) -> (Vec<usize>, Vec<String>) {
+    let mut ids = Vec::new();
+    let mut missing = Vec::new();
+    for tw_log in tw_logs {
+        match exported
+            .iter()
+            .find(|e| e.start == tw_log.start && e.end == tw_log.end)
+            .and_then(|e| e.id)
+        {
+            Some(id) => {
+                if id != tw_log.id {
+                    debug!("Interval @{} is now @{}", tw_log.id, id);
+                }
+                ids.push(id);
+            }
+            None => missing.push(format!("@{}", tw_log.id)),
+        }
+    }
+    (ids, missing)
+}
+
+/// Tags timewarrior intervals with a single `timew tag @1 @2 ... tag` call.
+/// timew may report problems on stderr, so anything written there is treated as an error too.
+pub fn tag_tw_logs(program: &str, ids: &[usize], tag: &str) -> Result<(), String> {
+    if ids.is_empty() {
+        return Ok(());
+    }
+    let ids: Vec<String> = ids.iter().map(|id| format!("@{}", id)).collect();
+    let output = std::process::Command::new(program)
+        .arg("tag")
+        .args(&ids)
+        .arg(tag)
         .output()
-    {
-        Ok(_) => Ok(()),
-        Err(e) => Err(format!(
-            "Error marking interval {} as uploaded: {}",
-            tw_log.id, e
+        .map_err(|e| format!("Error running timew to tag {}: {}", ids.join(" "), e))?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    match (output.status.success(), stderr.trim().is_empty()) {
+        (true, true) => Ok(()),
+        _ => Err(format!(
+            "Error tagging {} ({}): {}",
+            ids.join(" "),
+            output.status,
+            stderr.trim()
         )),
     }
 }
@@ -0,0 +1,56 @@
+use chrono::{Offset, TimeZone};
+use std::str::FromStr;
+use time::{OffsetDateTime, UtcOffset};
+
+/// The timezone worklogs are reported in.
+/// TimeWarrior timestamps are always UTC, so this only affects how worklog start times are rendered
+/// and which calendar day an interval falls on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Timezone {
+    /// A fixed offset from UTC, e.g. `+0100`
+    Fixed(UtcOffset),
+    /// An IANA timezone, e.g. `Europe/Berlin`, whose offset depends on the instant
+    Named(chrono_tz::Tz),
+}
+
+impl Default for Timezone {
+    fn default() -> Self {
+        Timezone::Fixed(UtcOffset::UTC)
+    }
+}
+
+impl FromStr for Timezone {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(offset) = UtcOffset::parse(s, "%z") {
+            return Ok(Timezone::Fixed(offset));
+        }
+        chrono_tz::Tz::from_str(s).map(Timezone::Named).map_err(|_| {
+            format!(
+                "Invalid timezone {}, expected an IANA timezone such as Europe/Berlin or an offset such as +0100",
+                s
+            )
+        })
+    }
+}
+
+impl Timezone {
+    /// The offset from UTC in effect at a given instant
+    pub fn offset_at(&self, instant: OffsetDateTime) -> UtcOffset {
+        match self {
+            Timezone::Fixed(offset) => *offset,
+            Timezone::Named(tz) => {
+                let utc = chrono::DateTime::from_timestamp(instant.unix_timestamp(), 0)
+                    .unwrap_or_default()
+                    .naive_utc();
+                UtcOffset::seconds(tz.offset_from_utc_datetime(&utc).fix().local_minus_utc())
+            }
+        }
+    }
+
+    /// Converts an instant to local time in this timezone
+    pub fn to_local(&self, instant: OffsetDateTime) -> OffsetDateTime {
+        instant.to_offset(self.offset_at(instant))
+    }
+}